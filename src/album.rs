@@ -1,7 +1,18 @@
 use eframe::egui;
+use std::collections::HashSet;
 use std::path::PathBuf;
-use crate::{UrlInfo, Single, Renderable, Downloadable};
-use crate::utils;
+use crate::{UrlInfo, Single, Renderable, Downloadable, Appendable};
+use crate::single::{self, FullVideos, SongEdit};
+use crate::rules::RuleEditor;
+use crate::metadata::Lookup;
+use crate::details::Details;
+use crate::utils::{self, Task};
+use crate::preview::{self, CoverPreview};
+use crate::settings::Settings;
+use crate::cover;
+use crate::extractor::Extractor;
+use crate::layout;
+use crate::loudness;
 
 extern crate sanitize_filename;
 
@@ -10,31 +21,68 @@ pub struct Album {
     pub album_title: String,
     pub album_artist: String,
     pub songs: Vec<Single>,
+    pub renumber: bool,
+    /// Put each disc in its own `Disc N` folder.
+    pub disc_folders: bool,
+    pub disc_url: String,
+    pub disc_status: String,
+    pub disc_fetch: Option<Task<Vec<UrlInfo>>>,
+    pub edit: Option<SongEdit>,
+    pub details: Details,
+    /// Set once the fetched songs were put in track order, or moved by hand before that.
+    /// Songs added later keep their place and never reorder the rest.
+    pub ordered: bool,
 
     pub use_thumbnail: bool,
-    pub cover_path: Option<PathBuf>
+    pub cover_path: Option<PathBuf>,
+    pub preview: CoverPreview,
+    pub show_cover_dialog: bool,
+
+    pub rules: RuleEditor,
+    pub lookup: Lookup
 }
 
 impl From<Vec<UrlInfo>> for Album {
     fn from(urls: Vec<UrlInfo>) -> Self {
         let mut album = Album::default();
         album.use_thumbnail = true;
+        album.rules = RuleEditor::load();
+        album.renumber = true;
         if let Some(title) = &urls[0].playlist {
             album.album_title = title.to_string();
         } else {
             album.album_title = "Album".to_string();
         }
-        album.album_artist = urls[0].artist.to_string();
+        album.album_artist = Extractor::of(&urls[0]).album_artist(&urls[0]);
+        album.lookup.title = album.album_title.to_string();
+        album.lookup.artist = album.album_artist.to_string();
+        album.details.queue(&urls);
         for url in urls {
             let song = Single::from(url);
             album.songs.push(song);
         }
+        album.sort_by_track_number();
+        // flat entries are sorted once their details are in
+        album.ordered = !album.details.is_loading();
         return album
     }
 }
 
+impl Appendable for Album {
+    fn append(&mut self, urls: Vec<UrlInfo>, split_chapters: bool) -> bool {
+        let disc = self.disc_total();
+        self.details.queue(&urls);
+        for mut song in single::songs_from(urls, split_chapters) {
+            song.album = self.album_title.to_string();
+            song.disc = disc;
+            self.songs.push(song);
+        }
+        return true
+    }
+}
+
 impl Renderable for Album {
-    fn render(&mut self, ui: &mut egui::Ui) {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
         ui.horizontal(|ui| {
             ui.label("Album Title");
             ui.text_edit_singleline(&mut self.album_title);
@@ -50,10 +98,25 @@ impl Renderable for Album {
             if ui.button("Apply").clicked() {
                 for song in &mut self.songs {
                     song.artist = self.album_artist.clone();
+                    song.inferred.artist = false;
                 }
             }
         });
+        if let Some(release) = self.lookup.render_release(ui, "album") {
+            self.album_title = release.title.to_string();
+            self.album_artist = release.artist.to_string();
+            single::apply_release(&mut self.songs, &release, true);
+        }
+        let thumbnail = self.songs.iter()
+            .find(|song| song.include)
+            .map(|song| song.thumbnail.to_string())
+            .unwrap_or_default();
         ui.horizontal(|ui| {
+            let source = CoverPreview::source_for(self.use_thumbnail, &self.cover_path, &thumbnail);
+            if self.preview.show(ui, &source, 96.0).clicked() {
+                self.show_cover_dialog = true;
+            }
+
             ui.checkbox(&mut self.use_thumbnail, "Use Thumbnail");
 
             if !self.use_thumbnail {
@@ -85,39 +148,208 @@ impl Renderable for Album {
                 }
             }
         });
+        preview::cover_dialog(
+            ui.ctx(),
+            egui::Id::new("album-cover"),
+            &self.album_title,
+            &mut self.show_cover_dialog,
+            &mut self.preview,
+            &settings.cover,
+            &mut self.use_thumbnail,
+            &mut self.cover_path
+        );
+        self.rules.render(ui, "album", &mut self.songs);
+        single::render_selection(ui, &mut self.songs);
+        ui.checkbox(&mut self.renumber, "Renumber Selected");
+        let next_disc = self.disc_total() + 1;
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.disc_url);
+            let fetch = ui.add_enabled(self.disc_fetch.is_none(), egui::Button::new(format!("Fetch Disc {}", next_disc)));
+            if fetch.clicked() && !self.disc_url.trim().is_empty() {
+                let disc_url = self.disc_url.to_string();
+                self.disc_fetch = Some(Task::spawn(ui.ctx(), move || crate::fetch_info(&disc_url, true)));
+                self.disc_status = String::default();
+            }
+            ui.checkbox(&mut self.disc_folders, "Disc Folders");
+            if let Some(urls) = self.disc_fetch.as_ref().and_then(|task| task.poll()) {
+                self.disc_fetch = None;
+                match urls {
+                    Ok(urls) => {
+                        self.details.queue(&urls);
+                        for url in urls {
+                            let mut song = Single::from(url);
+                            song.disc = next_disc;
+                            song.album = self.album_title.to_string();
+                            self.songs.push(song);
+                        }
+                        self.disc_url = String::default();
+                    },
+                    Err(e) => self.disc_status = e
+                }
+            }
+            if self.disc_fetch.is_some() {
+                ui.spinner();
+            }
+            ui.label(&self.disc_status);
+        });
+        let arrived = self.details.render(ui, &mut self.songs);
+        if arrived {
+            self.order_once_loaded();
+        }
+        // flat playlist entries have no artist to take the album artist from
+        if arrived && self.album_artist.is_empty() {
+            if let Some(song) = self.songs.iter().find(|song| !song.artist.is_empty() && !song.inferred.artist) {
+                self.album_artist = song.artist.to_string();
+                self.lookup.artist = song.artist.to_string();
+            }
+        }
         ui.separator();
-        for song in &mut self.songs {
-            song.render(ui);
+        let disc_total = self.disc_total();
+        let len = self.songs.len();
+        for (i, song) in self.songs.iter_mut().enumerate() {
+            SongEdit::render(ui, i, len, &mut self.edit);
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut song.include, "Include");
+                if disc_total > 1 || song.disc > 1 {
+                    ui.label("Disc");
+                    ui.add(egui::DragValue::new(&mut song.disc).clamp_range(1..=disc_total + 1));
+                }
+            });
+            song.render(ui, settings);
             ui.separator();
         }
+        if let Some(edit) = self.edit.take() {
+            edit.apply(&mut self.songs);
+            self.ordered = true;
+        }
     }
 }
 
 impl Downloadable for Album {
-    fn download(&self, base_dir: &PathBuf) {
-        let download_dir = base_dir.join("albums").join(sanitize_filename::sanitize(&self.album_title));
-        for (i, song) in self.songs.iter().enumerate() {
-            let output_format = format!("{}---{}.%(ext)s", sanitize_filename::sanitize(&song.track), sanitize_filename::sanitize(&song.artist));
-            utils::download_video(&song.webpage_url, &output_format, download_dir.to_str().unwrap(), song.use_thumbnail);
-
-            let mp3_name = output_format.replace("%(ext)s", "mp3");
-            let mp3_path = &download_dir.join(mp3_name);
-
-            if let Some(path) = &song.cover_path {
-                let cover_name = path.file_name().unwrap();
-                let new_path = &download_dir.join(cover_name);
-                let _ = std::fs::copy(path, new_path);
-
-                let cover_path = utils::convert_jpg(&new_path);
-                song.tag(mp3_path, &cover_path, Some(i + 1));
-            } else if song.use_thumbnail {
-                let cover_name = output_format.replace("%(ext)s", "webp");
-                let cover_path = &download_dir.join(cover_name);
-                let cover_path = utils::convert_jpg(&cover_path);
-                song.tag(mp3_path, &cover_path, Some(i + 1));
+    fn ready(&self) -> bool {
+        !self.details.is_loading()
+    }
+
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        let mut errors = Vec::new();
+        let flat_dir = base_dir.join("albums").join(utils::sanitize_or(&self.album_title, "Album"));
+        let job = utils::Job::new(base_dir);
+        let mut full_videos = FullVideos::new();
+        let mut taken = HashSet::new();
+        let mut album_cover = None;
+        let mut kept = Vec::new();
+        let positions = single::album_positions(&self.songs, self.renumber);
+        for (song, position) in self.songs.iter().zip(positions) {
+            let position = match position {
+                Some(position) => position,
+                None => continue
+            };
+
+            let stem = utils::unique_stem(utils::song_stem(&song.track, &song.artist), &mut taken);
+            let mp3_path = match song.download_audio(&job.dir, &stem, settings, &mut full_videos, &mut errors) {
+                Some(mp3_path) => mp3_path,
+                None => continue
+            };
+
+            let cover_path = song.prepare_cover(&job.dir, &stem, &settings.cover).unwrap_or_else(|e| {
+                errors.push(song.problem(e));
+                None
+            });
+            let steps = [
+                song.tag(&mp3_path, cover_path.as_ref(), Some(position), Some(&self.album_artist)),
+                song.tag_subtitles(&mp3_path, &job.dir, &stem)
+            ];
+            errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| song.problem(e)));
+            let dest = layout::album_song_path(settings.layout, base_dir, &flat_dir, &stem, song.place(&self.album_artist, Some(position)), self.disc_folders);
+            match job.keep(&mp3_path, &dest) {
+                Ok(mp3_path) => kept.push(mp3_path),
+                Err(e) => errors.push(song.problem(e))
+            }
+
+            if album_cover.is_none() {
+                album_cover = cover_path;
             }
         }
 
-        utils::cleanup(&download_dir);
+        errors.extend(loudness::tag_replaygain(&kept, true, &settings.loudness));
+
+        // the folder the album ended up in, which depends on the layout
+        let album_dir = kept.first().and_then(|path| layout::album_dir(path, self.disc_folders));
+        if let (Some(album_cover), Some(album_dir)) = (&album_cover, album_dir) {
+            if let Err(e) = cover::write_folder_art(album_cover, album_dir, settings.folder_art, settings.cover.jpeg_quality) {
+                errors.push(e);
+            }
+        }
+        return errors
+    }
+}
+
+impl Album {
+    /// Sites with track numbers don't always list tracks in order, songs are put in order
+    /// within their disc when every song has one.
+    fn sort_by_track_number(&mut self) {
+        if self.songs.iter().all(|song| song.track_number.is_some()) {
+            self.songs.sort_by_key(|song| (song.disc, song.track_number));
+        }
+    }
+
+    /// Flat playlist entries have no track numbers, the order is only known once all details are in.
+    /// Only done for the first fetch, see `ordered`.
+    fn order_once_loaded(&mut self) {
+        if !self.details.is_loading() && !self.ordered {
+            self.sort_by_track_number();
+            self.ordered = true;
+        }
+    }
+
+    fn disc_total(&self) -> usize {
+        self.songs.iter().map(|song| song.disc).max().unwrap_or(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(disc: usize, track_number: Option<usize>) -> Single {
+        let mut song = Single::default();
+        song.disc = disc;
+        song.track_number = track_number;
+        song.track = format!("{}-{}", disc, track_number.unwrap_or_default());
+        return song
+    }
+
+    fn order(album: &Album) -> Vec<&str> {
+        album.songs.iter().map(|song| song.track.as_str()).collect()
+    }
+
+    #[test]
+    fn songs_sort_by_disc_and_track_number() {
+        let mut album = Album::default();
+        album.songs = vec![song(2, Some(1)), song(1, Some(2)), song(1, Some(1))];
+        album.sort_by_track_number();
+        assert_eq!(order(&album), ["1-1", "1-2", "2-1"]);
+    }
+
+    #[test]
+    fn songs_without_track_numbers_keep_their_order() {
+        let mut album = Album::default();
+        album.songs = vec![song(1, Some(2)), song(1, None), song(1, Some(1))];
+        album.sort_by_track_number();
+        assert_eq!(order(&album), ["1-2", "1-0", "1-1"]);
+    }
+
+    #[test]
+    fn songs_are_only_ordered_once() {
+        let mut album = Album::default();
+        album.songs = vec![song(1, Some(2)), song(1, Some(1))];
+        album.order_once_loaded();
+        assert_eq!(order(&album), ["1-1", "1-2"]);
+
+        // moved by hand, then another disc's details came in
+        SongEdit::Down(0).apply(&mut album.songs);
+        album.songs.push(song(2, Some(1)));
+        album.order_once_loaded();
+        assert_eq!(order(&album), ["1-2", "1-1", "2-1"]);
     }
 }
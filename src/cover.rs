@@ -0,0 +1,269 @@
+use eframe::egui;
+use image::imageops::FilterType;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct CoverOptions {
+    pub square_crop: bool,
+    pub trim_letterbox: bool,
+    /// Longest side in pixels, 0 keeps the original size.
+    pub max_dimension: u32,
+    pub jpeg_quality: u8
+}
+
+impl Default for CoverOptions {
+    fn default() -> Self {
+        CoverOptions {
+            square_crop: false,
+            trim_letterbox: false,
+            max_dimension: 0,
+            jpeg_quality: 90
+        }
+    }
+}
+
+impl CoverOptions {
+    pub fn is_noop(&self) -> bool {
+        !self.square_crop && !self.trim_letterbox && self.max_dimension == 0
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.trim_letterbox, "Trim Letterbox Bars");
+        ui.checkbox(&mut self.square_crop, "Center Square Crop");
+        ui.horizontal(|ui| {
+            ui.label("Max Size");
+            ui.add(egui::DragValue::new(&mut self.max_dimension)
+                .clamp_range(0..=4096)
+                .suffix(" px"));
+            if self.max_dimension == 0 {
+                ui.weak("original");
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("JPEG Quality");
+            ui.add(egui::Slider::new(&mut self.jpeg_quality, 1..=100));
+        });
+    }
+}
+
+/// Album art file kept next to the songs for players that read it from the folder.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum FolderArt {
+    #[default]
+    None,
+    Cover,
+    Folder,
+    Both
+}
+
+pub const FOLDER_ART_NAMES: [&str; 2] = ["cover.jpg", "folder.jpg"];
+
+impl FolderArt {
+    pub fn file_names(&self) -> &'static [&'static str] {
+        match self {
+            FolderArt::None => &[],
+            FolderArt::Cover => &FOLDER_ART_NAMES[..1],
+            FolderArt::Folder => &FOLDER_ART_NAMES[1..],
+            FolderArt::Both => &FOLDER_ART_NAMES
+        }
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Folder Art")
+            .selected_text(match self {
+                FolderArt::None => "None",
+                FolderArt::Cover => "cover.jpg",
+                FolderArt::Folder => "folder.jpg",
+                FolderArt::Both => "cover.jpg + folder.jpg"
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(self, FolderArt::None, "None");
+                ui.selectable_value(self, FolderArt::Cover, "cover.jpg");
+                ui.selectable_value(self, FolderArt::Folder, "folder.jpg");
+                ui.selectable_value(self, FolderArt::Both, "cover.jpg + folder.jpg");
+            });
+    }
+}
+
+/// Writes the already prepared `cover` into `dir` as the folder art files, always as jpg.
+/// Files that are already there are kept, they may be the user's own art.
+pub fn write_folder_art(cover: &PathBuf, dir: &Path, folder_art: FolderArt, quality: u8) -> Result<(), String> {
+    let missing: Vec<&str> = folder_art.file_names().iter()
+        .copied()
+        .filter(|name| !dir.join(name).exists())
+        .collect();
+    if missing.is_empty() {
+        return Ok(())
+    }
+
+    let image = image::open(cover).map_err(|e| format!("folder art: {}", e))?;
+    for name in missing {
+        write_jpg(&image, &dir.join(name), quality).map_err(|e| format!("folder art: {}", e))?;
+    }
+    return Ok(())
+}
+
+/// Thumbnail yt-dlp wrote for `stem`, whichever image format the site served.
+pub fn find_thumbnail(dir: &Path, stem: &str) -> Option<PathBuf> {
+    ["webp", "jpg", "jpeg", "png"].iter()
+        .map(|extension| dir.join(format!("{}.{}", stem, extension)))
+        .find(|path| path.exists())
+}
+
+pub fn mime_type(bytes: &[u8]) -> &'static str {
+    match image::guess_format(bytes) {
+        Ok(ImageFormat::Png) => "image/png",
+        _ => "image/jpeg"
+    }
+}
+
+/// Gets the image at `path` ready for embedding and returns where it ended up.
+/// PNG and JPEG are kept as they are when no cover option is enabled, anything else
+/// (webp thumbnails) is converted to jpg. Processed PNGs stay PNG.
+pub fn prepare(path: &PathBuf, options: &CoverOptions) -> Result<PathBuf, String> {
+    let bytes = fs::read(path).map_err(|e| format!("cover: {}", e))?;
+
+    let format = image::guess_format(&bytes).ok();
+    let native = matches!(format, Some(ImageFormat::Png) | Some(ImageFormat::Jpeg));
+    if native && options.is_noop() {
+        return Ok(path.to_path_buf())
+    }
+
+    let image = image::load_from_memory(&bytes).map_err(|e| format!("cover: {}", e))?;
+    let image = transform(image, options);
+
+    if format == Some(ImageFormat::Png) {
+        let new_path = path.with_extension("png");
+        image.save_with_format(&new_path, ImageFormat::Png).map_err(|e| format!("cover: {}", e))?;
+        return Ok(new_path)
+    }
+    let new_path = path.with_extension("jpg");
+    write_jpg(&image, &new_path, options.jpeg_quality).map_err(|e| format!("cover: {}", e))?;
+    return Ok(new_path)
+}
+
+pub fn transform(mut image: DynamicImage, options: &CoverOptions) -> DynamicImage {
+    if options.trim_letterbox {
+        let (x, y, width, height) = content_bounds(&image);
+        image = image.crop_imm(x, y, width, height);
+    }
+
+    if options.square_crop {
+        let (width, height) = image.dimensions();
+        let side = width.min(height);
+        image = image.crop_imm((width - side) / 2, (height - side) / 2, side, side);
+    }
+
+    let max = options.max_dimension;
+    if max > 0 && (image.width() > max || image.height() > max) {
+        image = image.resize(max, max, FilterType::Lanczos3);
+    }
+
+    return image
+}
+
+pub fn write_jpg(image: &DynamicImage, path: &PathBuf, quality: u8) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = JpegEncoder::new_with_quality(BufWriter::new(file), quality);
+    let rgb = image.to_rgb8();
+    encoder.encode(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8)
+        .map_err(|e| e.to_string())
+}
+
+// rows/columns darker than this are treated as bars
+const BAR_LUMA: u8 = 24;
+
+/// Bounds `(x, y, width, height)` of the image with black bars on any side removed.
+fn content_bounds(image: &DynamicImage) -> (u32, u32, u32, u32) {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+
+    let row_dark = |y: u32| (0..width).all(|x| luma.get_pixel(x, y)[0] <= BAR_LUMA);
+    let column_dark = |x: u32| (0..height).all(|y| luma.get_pixel(x, y)[0] <= BAR_LUMA);
+
+    let top = (0..height).find(|y| !row_dark(*y));
+    let top = match top {
+        Some(top) => top,
+        // entirely dark, nothing to trim against
+        None => return (0, 0, width, height)
+    };
+    let bottom = (0..height).rev().find(|y| !row_dark(*y)).unwrap_or(top);
+    let left = (0..width).find(|x| !column_dark(*x)).unwrap_or(0);
+    let right = (0..width).rev().find(|x| !column_dark(*x)).unwrap_or(width - 1);
+
+    (left, top, right - left + 1, bottom - top + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_art_keeps_existing_files() {
+        let dir = std::env::temp_dir().join(format!("bebops-test-folder-art-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let cover = dir.join("new.png");
+        image::RgbImage::new(4, 4).save(&cover).unwrap();
+        fs::write(dir.join("cover.jpg"), "foreign").unwrap();
+
+        write_folder_art(&cover, &dir, FolderArt::Both, 90).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("cover.jpg")).unwrap(), "foreign");
+        assert!(image::open(dir.join("folder.jpg")).is_ok());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// `width` × `height` grey image with black bars `bar` pixels high above and below it.
+    fn letterboxed(width: u32, height: u32, bar: u32) -> DynamicImage {
+        let image = image::RgbImage::from_fn(width, height, |_, y| {
+            if y < bar || y >= height - bar { image::Rgb([0, 0, 0]) } else { image::Rgb([128, 128, 128]) }
+        });
+        DynamicImage::ImageRgb8(image)
+    }
+
+    fn options(square_crop: bool, trim_letterbox: bool, max_dimension: u32) -> CoverOptions {
+        CoverOptions { square_crop, trim_letterbox, max_dimension, ..CoverOptions::default() }
+    }
+
+    #[test]
+    fn letterbox_bars_are_trimmed() {
+        let image = transform(letterboxed(160, 90, 15), &options(false, true, 0));
+        assert_eq!(image.dimensions(), (160, 60));
+        assert!(image.to_luma8().pixels().all(|pixel| pixel[0] == 128));
+    }
+
+    #[test]
+    fn square_crop_keeps_the_center() {
+        // left and right thirds are black, the middle is white
+        let image = image::RgbImage::from_fn(90, 30, |x, _| {
+            if (30..60).contains(&x) { image::Rgb([255, 255, 255]) } else { image::Rgb([0, 0, 0]) }
+        });
+        let image = transform(DynamicImage::ImageRgb8(image), &options(true, false, 0));
+        assert_eq!(image.dimensions(), (30, 30));
+        assert!(image.to_luma8().pixels().all(|pixel| pixel[0] == 255));
+    }
+
+    #[test]
+    fn resize_caps_the_longest_side() {
+        let image = transform(letterboxed(400, 200, 0), &options(false, false, 100));
+        assert_eq!(image.dimensions(), (100, 50));
+        // smaller images aren't scaled up
+        let image = transform(letterboxed(80, 40, 0), &options(false, false, 100));
+        assert_eq!(image.dimensions(), (80, 40));
+    }
+
+    #[test]
+    fn images_without_bars_are_left_alone() {
+        let image = transform(letterboxed(160, 90, 0), &options(false, true, 0));
+        assert_eq!(image.dimensions(), (160, 90));
+        // all dark, nothing to measure bars against
+        let image = transform(DynamicImage::ImageRgb8(image::RgbImage::new(16, 9)), &options(false, true, 0));
+        assert_eq!(image.dimensions(), (16, 9));
+    }
+}
@@ -0,0 +1,100 @@
+use eframe::egui;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use crate::{UrlInfo, Single};
+
+/// Full `yt-dlp -j` info for songs fetched with `--flat-playlist`, loaded one video at a time
+/// in the background and applied to the songs as it arrives.
+#[derive(Default)]
+pub struct Details {
+    queued: Vec<String>,
+    pending: Option<Receiver<Result<UrlInfo, String>>>,
+    total: usize,
+    loaded: usize,
+    errors: Vec<String>
+}
+
+impl Details {
+    /// Queues the flat entries of `urls`, fully fetched entries are skipped.
+    pub fn queue(&mut self, urls: &[UrlInfo]) {
+        let flat: Vec<String> = urls.iter()
+            .filter(|url| url.is_flat())
+            .map(|url| url.webpage_url.to_string())
+            .collect();
+        self.total += flat.len();
+        self.queued.extend(flat);
+    }
+
+    /// Whether details are still on their way, songs aren't complete until they are in.
+    pub fn is_loading(&self) -> bool {
+        self.loaded < self.total
+    }
+
+    /// Returns whether any details came in since the last frame.
+    pub fn render(&mut self, ui: &mut egui::Ui, songs: &mut [Single]) -> bool {
+        let loaded = self.loaded;
+        self.poll(ui.ctx(), songs);
+
+        if self.is_loading() {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(format!("Loading details {}/{}", self.loaded, self.total));
+            });
+        }
+        if !self.errors.is_empty() {
+            ui.collapsing(format!("{} songs without details", self.errors.len()), |ui| {
+                for e in &self.errors {
+                    ui.label(e);
+                }
+            });
+        }
+        return self.loaded > loaded
+    }
+
+    fn poll(&mut self, ctx: &egui::Context, songs: &mut [Single]) {
+        if let Some(pending) = &self.pending {
+            loop {
+                match pending.try_recv() {
+                    Ok(Ok(url)) => {
+                        self.loaded += 1;
+                        let fresh = Single::from(url);
+                        for song in songs.iter_mut().filter(|song| song.video_id == fresh.video_id) {
+                            song.update_from(&fresh);
+                        }
+                    },
+                    Ok(Err(e)) => {
+                        self.loaded += 1;
+                        self.errors.push(e);
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.pending = None;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if self.pending.is_none() && !self.queued.is_empty() {
+            self.pending = Some(load(ctx, std::mem::take(&mut self.queued)));
+        }
+    }
+}
+
+fn load(ctx: &egui::Context, webpage_urls: Vec<String>) -> Receiver<Result<UrlInfo, String>> {
+    let (sender, receiver) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        for webpage_url in webpage_urls {
+            let info = crate::fetch_info(&webpage_url, false)
+                .map(|urls| urls.into_iter().next().unwrap())
+                .map_err(|e| format!("{}: {}", webpage_url, e));
+            // the content was cleared
+            if sender.send(info).is_err() {
+                return
+            }
+            ctx.request_repaint();
+        }
+    });
+    return receiver
+}
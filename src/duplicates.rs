@@ -0,0 +1,341 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use crate::library::Track;
+
+// fingerprints this similar (share of matching bits) are the same recording
+const FINGERPRINT_MATCH: f32 = 0.85;
+// only recordings this close in length are compared
+const DURATION_SLACK: f32 = 5.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reason {
+    VideoId,
+    ArtistTitle,
+    Fingerprint
+}
+
+impl Reason {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Reason::VideoId => "same video",
+            Reason::ArtistTitle => "same artist and title",
+            Reason::Fingerprint => "same audio"
+        }
+    }
+}
+
+/// Copies of one song, as indices into the scanned tracks. `keep` is the copy the others
+/// are replaced with, albums are preferred over singles, and singles over playlists.
+pub struct DuplicateGroup {
+    pub reason: Reason,
+    pub tracks: Vec<usize>,
+    pub keep: usize
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Resolution {
+    #[default]
+    Hardlink,
+    Symlink,
+    /// Only for copies in playlist folders, the copy is deleted and the playlist's
+    /// `.m3u8` points at the kept file instead.
+    Reference
+}
+
+/// Lowercased, without video noise in brackets, featured artists and punctuation,
+/// so "Song (Official Video)" and "song" compare equal. Other bracketed parts are kept,
+/// "Song (Live)" or "Song [Remix]" is another version, not a copy.
+pub fn normalize(text: &str) -> String {
+    static BRACKETS: OnceLock<Regex> = OnceLock::new();
+    static NOISE: OnceLock<Regex> = OnceLock::new();
+    static FEATURING: OnceLock<Regex> = OnceLock::new();
+    let brackets = BRACKETS.get_or_init(|| Regex::new(r"[\(\[\{]([^\)\]\}]*)[\)\]\}]").unwrap());
+    let noise = NOISE.get_or_init(|| Regex::new(r"(?i)\b(official|video|audio|lyrics?|visuali[sz]er|hd|hq|4k|m/?v|feat\.?|ft\.?|featuring)(\b|$)").unwrap());
+    let featuring = FEATURING.get_or_init(|| Regex::new(r"(?i)\s(feat\.?|ft\.?|featuring)\s.*$").unwrap());
+    let text = brackets.replace_all(text, |caps: &regex::Captures| {
+        if noise.is_match(&caps[1]) { " ".to_string() } else { format!(" {} ", &caps[1]) }
+    });
+    let text = featuring.replace(&text, "");
+    text.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Key two copies of the same song share, even when downloaded from different videos.
+pub fn song_key(artist: &str, title: &str) -> Option<String> {
+    let title = normalize(title);
+    if title.is_empty() {
+        return None
+    }
+    Some(format!("{}\u{0}{}", normalize(artist), title))
+}
+
+/// Groups copies of the same song. Files that are already links to each other don't count.
+pub fn find(tracks: &[Track], fingerprints: bool) -> Vec<DuplicateGroup> {
+    let identities: Vec<String> = tracks.iter().map(|track| identity(&track.path)).collect();
+    let mut groups = Vec::new();
+    let mut grouped = vec![false; tracks.len()];
+
+    let mut by_video: HashMap<&str, Vec<usize>> = HashMap::new();
+    let mut by_song: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, track) in tracks.iter().enumerate() {
+        if !track.video_id.is_empty() {
+            by_video.entry(&track.video_id).or_default().push(i);
+        }
+        if let Some(key) = song_key(&track.artist, &track.title) {
+            by_song.entry(key).or_default().push(i);
+        }
+    }
+
+    let mut candidates: Vec<(Reason, Vec<usize>)> = Vec::new();
+    candidates.extend(by_video.into_values().map(|indices| (Reason::VideoId, indices)));
+    candidates.extend(by_song.into_values().map(|indices| (Reason::ArtistTitle, indices)));
+    if fingerprints {
+        candidates.extend(fingerprint_groups(tracks).into_iter().map(|indices| (Reason::Fingerprint, indices)));
+    }
+
+    for (reason, indices) in candidates {
+        let indices: Vec<usize> = indices.into_iter().filter(|i| !grouped[*i]).collect();
+        let mut distinct: Vec<&str> = indices.iter().map(|i| identities[*i].as_str()).collect();
+        distinct.sort();
+        distinct.dedup();
+        if distinct.len() < 2 {
+            continue;
+        }
+
+        for i in &indices {
+            grouped[*i] = true;
+        }
+        let keep = *indices.iter().min_by_key(|i| (location_rank(&tracks[**i]), tracks[**i].path.to_path_buf())).unwrap();
+        groups.push(DuplicateGroup { reason, tracks: indices, keep });
+    }
+
+    groups.sort_by_key(|group| tracks[group.keep].path.to_path_buf());
+    return groups
+}
+
+/// Lower is a better home for a song, album tracks first, playlist folders last.
+/// Album tracks are told apart by their tags, so it works the same for every layout.
+pub fn location_rank(track: &Track) -> usize {
+    if track.path.components().any(|component| component.as_os_str() == "playlists") {
+        2
+    } else if !track.album.is_empty() && track.track_no.is_some() {
+        0
+    } else {
+        1
+    }
+}
+
+/// Same for a file and every hard or symbolic link to it.
+fn identity(path: &Path) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Ok(metadata) = fs::metadata(path) {
+            return format!("{}:{}", metadata.dev(), metadata.ino())
+        }
+    }
+    return fs::canonicalize(path).unwrap_or(path.to_path_buf()).to_string_lossy().to_string()
+}
+
+#[derive(Deserialize)]
+struct Fpcalc {
+    duration: f32,
+    fingerprint: Vec<u32>
+}
+
+/// Raw Chromaprint fingerprint from `fpcalc`, which has to be in the $PATH.
+fn fingerprint(path: &Path) -> Option<Fpcalc> {
+    let output = Command::new("fpcalc")
+        .args(["-raw", "-json"])
+        .arg(path)
+        .output()
+        .ok()?;
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// Share of matching bits between two raw fingerprints, compared from the start.
+fn similarity(a: &[u32], b: &[u32]) -> f32 {
+    let len = a.len().min(b.len());
+    if len == 0 {
+        return 0.0
+    }
+    let differing: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+    return 1.0 - differing as f32 / (len as f32 * 32.0)
+}
+
+fn fingerprint_groups(tracks: &[Track]) -> Vec<Vec<usize>> {
+    let prints: Vec<Option<Fpcalc>> = tracks.iter().map(|track| fingerprint(&track.path)).collect();
+    return group_prints(&prints)
+}
+
+/// Groups the indices of matching fingerprints. Sorted by duration, each one is only
+/// compared with the ones after it that are still within `DURATION_SLACK`.
+fn group_prints(prints: &[Option<Fpcalc>]) -> Vec<Vec<usize>> {
+    let mut sorted: Vec<(usize, &Fpcalc)> = prints.iter()
+        .enumerate()
+        .filter_map(|(i, print)| Some((i, print.as_ref()?)))
+        .collect();
+    sorted.sort_by(|(_, a), (_, b)| a.duration.total_cmp(&b.duration));
+
+    let mut group_of: Vec<Option<usize>> = vec![None; prints.len()];
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (n, (i, a)) in sorted.iter().enumerate() {
+        let close = sorted[n + 1..].iter().take_while(|(_, b)| b.duration - a.duration <= DURATION_SLACK);
+        for (j, b) in close {
+            if group_of[*j].is_some() || similarity(&a.fingerprint, &b.fingerprint) < FINGERPRINT_MATCH {
+                continue;
+            }
+
+            let group = match group_of[*i] {
+                Some(group) => group,
+                None => {
+                    groups.push(vec![*i]);
+                    group_of[*i] = Some(groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+            groups[group].push(*j);
+            group_of[*j] = Some(group);
+        }
+    }
+    return groups
+}
+
+/// Replaces `duplicate` with `resolution` pointing at `keep`.
+pub fn resolve(keep: &Path, duplicate: &Path, resolution: Resolution) -> Result<(), String> {
+    // already the same file, removing `duplicate` could take `keep` with it
+    if identity(keep) == identity(duplicate) {
+        return Ok(())
+    }
+
+    match resolution {
+        Resolution::Hardlink => replace_with_link(duplicate, |link| fs::hard_link(keep, link)),
+        Resolution::Symlink => replace_with_link(duplicate, |link| symlink(keep, link)),
+        Resolution::Reference => {
+            let folder = duplicate.parent().ok_or("No parent folder")?;
+            let playlists = m3u8_files(folder);
+            if playlists.is_empty() {
+                return Err(format!("{} is not in a playlist folder", duplicate.display()))
+            }
+            for playlist in playlists {
+                replace_in_playlist(&playlist, duplicate, keep)?;
+            }
+            fs::remove_file(duplicate).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Creates the link next to `duplicate` first and then renames it over it,
+/// so `duplicate` is only gone once the link is in its place.
+fn replace_with_link(duplicate: &Path, create_link: impl FnOnce(&Path) -> std::io::Result<()>) -> Result<(), String> {
+    let mut name = duplicate.file_name().ok_or("Not a file")?.to_os_string();
+    name.push(".bebops-link");
+    let link = duplicate.with_file_name(name);
+    // left over from an interrupted run
+    let _ = fs::remove_file(&link);
+
+    create_link(&link).map_err(|e| e.to_string())?;
+    if let Err(e) = fs::rename(&link, duplicate) {
+        let _ = fs::remove_file(&link);
+        return Err(e.to_string())
+    }
+    return Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+pub fn m3u8_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("m3u8")))
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Points every line of `playlist` that is `from` at `to` instead.
+pub fn replace_in_playlist(playlist: &Path, from: &Path, to: &Path) -> Result<(), String> {
+    let contents = fs::read_to_string(playlist).map_err(|e| e.to_string())?;
+    let from = from.to_string_lossy();
+    let to = to.to_string_lossy();
+    let mut replaced = String::default();
+    for line in contents.lines() {
+        replaced.push_str(if line == from { &to } else { line });
+        replaced.push('\n');
+    }
+    fs::write(playlist, replaced).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_video_noise_only() {
+        assert_eq!(normalize("Song (Official Video)"), "song");
+        assert_eq!(normalize("Song [Lyrics]"), "song");
+        assert_eq!(normalize("Song (Official Music Video) [HD]"), "song");
+        assert_eq!(normalize("Song (feat. Someone)"), "song");
+        assert_eq!(normalize("Song ft. Someone"), "song");
+        assert_eq!(normalize("Song (Live)"), "song live");
+        assert_eq!(normalize("Song [Acoustic Version]"), "song acoustic version");
+        assert_ne!(song_key("Artist", "Song (Remix)"), song_key("Artist", "Song (Official Audio)"));
+    }
+
+    #[test]
+    fn fingerprints_match_within_the_duration_slack() {
+        let print = |duration: f32, fingerprint: &[u32]| Some(Fpcalc { duration, fingerprint: fingerprint.to_vec() });
+        let prints = [
+            print(200.0, &[1, 2, 3, 4]),
+            print(100.0, &[9, 9, 9, 9]),
+            None,
+            // same audio, a few seconds longer
+            print(203.0, &[1, 2, 3, 5]),
+            // same fingerprint but much longer, another recording
+            print(260.0, &[1, 2, 3, 4]),
+            print(101.0, &[u32::MAX, 0, u32::MAX, 0])
+        ];
+        assert_eq!(group_prints(&prints), [vec![0, 3]]);
+    }
+
+    #[test]
+    fn hardlinks_replace_the_duplicate() {
+        let dir = std::env::temp_dir().join(format!("bebops-test-resolve-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (keep, duplicate) = (dir.join("keep.mp3"), dir.join("duplicate.mp3"));
+        fs::write(&keep, "kept").unwrap();
+        fs::write(&duplicate, "copy").unwrap();
+
+        resolve(&keep, &duplicate, Resolution::Hardlink).unwrap();
+        assert_eq!(fs::read_to_string(&duplicate).unwrap(), "kept");
+        assert_eq!(identity(&keep), identity(&duplicate));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // a link that can't be made leaves the duplicate alone
+        let (missing, other) = (dir.join("missing.mp3"), dir.join("other.mp3"));
+        fs::write(&other, "other").unwrap();
+        assert!(resolve(&missing, &other, Resolution::Hardlink).is_err());
+        assert_eq!(fs::read_to_string(&other).unwrap(), "other");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
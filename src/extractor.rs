@@ -0,0 +1,174 @@
+use crate::UrlInfo;
+
+/// Sites whose `yt-dlp` json is read differently, going by `extractor_key`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Extractor {
+    YouTube,
+    /// Gives artist, album, track number and release date for every track.
+    Bandcamp,
+    /// Tracks are posted by the artist, the uploader is the artist.
+    SoundCloud,
+    Other
+}
+
+impl Extractor {
+    pub fn of(url: &UrlInfo) -> Self {
+        let key = url.extractor_key.to_lowercase();
+        if key.starts_with("youtube") {
+            Extractor::YouTube
+        } else if key.starts_with("bandcamp") {
+            Extractor::Bandcamp
+        } else if key.starts_with("soundcloud") {
+            Extractor::SoundCloud
+        } else {
+            Extractor::Other
+        }
+    }
+
+    /// Artist to fall back on when yt-dlp doesn't give one and the title doesn't have it either.
+    pub fn uploader_artist(&self, url: &UrlInfo) -> String {
+        let uploader = if url.uploader.is_empty() { &url.channel } else { &url.uploader };
+        match self {
+            Extractor::YouTube => uploader.trim_end_matches(" - Topic").to_string(),
+            Extractor::Bandcamp if !url.album_artist.is_empty() => url.album_artist.to_string(),
+            _ => uploader.to_string()
+        }
+    }
+
+    /// Artist of a whole album or set.
+    pub fn album_artist(&self, url: &UrlInfo) -> String {
+        match self {
+            Extractor::YouTube => url.artist.to_string(),
+            _ if !url.album_artist.is_empty() => url.album_artist.to_string(),
+            _ if !url.artist.is_empty() => url.artist.to_string(),
+            _ => self.uploader_artist(url)
+        }
+    }
+
+    /// "Artist - Title" in titles is only guessed at where the site doesn't have proper fields.
+    pub fn parses_titles(&self) -> bool {
+        *self != Extractor::Bandcamp
+    }
+
+    /// Release year, if the site has one. YouTube upload dates are often long after the release,
+    /// SoundCloud tracks are usually released when they are uploaded.
+    pub fn year(&self, url: &UrlInfo) -> Option<String> {
+        if let Some(year) = url.release_year {
+            return Some(year.to_string())
+        }
+        let date = match self {
+            Extractor::SoundCloud => url.release_date.as_ref().or(url.upload_date.as_ref()),
+            _ => url.release_date.as_ref()
+        };
+        date.and_then(|date| date.get(..4)).map(|year| year.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Single;
+    use crate::album::Album;
+
+    // trimmed down `yt-dlp -j` output, one track of a Bandcamp album
+    const BANDCAMP: &str = r#"{"id": "2813460583", "title": "Arpeggi", "track": "Arpeggi", "track_number": 2, "track_id": "2813460583",
+        "artist": "Radiohead", "album": "In Rainbows", "album_artist": "Radiohead", "uploader": "Radiohead", "uploader_id": "radiohead",
+        "release_year": null, "release_date": "20071010", "upload_date": "20071010", "duration": 318.4,
+        "thumbnail": "https://f4.bcbits.com/img/a1_10.jpg", "webpage_url": "https://radiohead.bandcamp.com/track/arpeggi",
+        "extractor": "Bandcamp", "extractor_key": "Bandcamp", "playlist": "In Rainbows", "playlist_index": 2}"#;
+
+    const SOUNDCLOUD: &str = r#"{"id": "1126894390", "title": "Night Drive", "uploader": "Some Producer", "uploader_id": "81627",
+        "artist": null, "track": null, "genre": "Electronic", "duration": 212.5, "upload_date": "20210903", "release_date": null,
+        "thumbnail": "https://i1.sndcdn.com/artworks-000-t500x500.jpg", "webpage_url": "https://soundcloud.com/some-producer/night-drive",
+        "extractor": "soundcloud", "extractor_key": "Soundcloud"}"#;
+
+    const YOUTUBE: &str = r#"{"id": "dQw4w9WgXcQ", "title": "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+        "channel": "Rick Astley", "uploader": "Rick Astley", "upload_date": "20091025", "release_year": null, "duration": 212,
+        "artist": null, "track": null, "album": null, "thumbnail": "https://i.ytimg.com/vi/dQw4w9WgXcQ/maxresdefault.jpg",
+        "webpage_url": "https://www.youtube.com/watch?v=dQw4w9WgXcQ", "extractor": "youtube", "extractor_key": "Youtube"}"#;
+
+    const YOUTUBE_MUSIC: &str = r#"{"id": "Zi_XLOBDo_Y", "title": "Billie Jean", "channel": "Michael Jackson - Topic",
+        "uploader": "Michael Jackson - Topic", "artist": "Michael Jackson", "track": "Billie Jean", "album": "Thriller",
+        "release_year": 1982, "release_date": null, "upload_date": "20150801", "duration": 294,
+        "webpage_url": "https://www.youtube.com/watch?v=Zi_XLOBDo_Y", "extractor_key": "Youtube"}"#;
+
+    fn info(json: &str) -> UrlInfo {
+        let mut url: UrlInfo = serde_json::from_str(json).unwrap();
+        url.fill_fallbacks();
+        return url
+    }
+
+    #[test]
+    fn bandcamp_fields_are_used_as_they_are() {
+        let url = info(BANDCAMP);
+        let extractor = Extractor::of(&url);
+        assert_eq!(extractor, Extractor::Bandcamp);
+        assert_eq!(extractor.album_artist(&url), "Radiohead");
+        assert_eq!(extractor.year(&url).as_deref(), Some("2007"));
+
+        let song = Single::from(info(BANDCAMP));
+        assert_eq!(song.track, "Arpeggi");
+        assert_eq!(song.artist, "Radiohead");
+        assert_eq!(song.album, "In Rainbows");
+        assert_eq!(song.year, "2007");
+        assert!(!song.inferred.track && !song.inferred.artist);
+    }
+
+    #[test]
+    fn bandcamp_albums_follow_track_numbers() {
+        let tracks = [(3, "House of Cards"), (1, "15 Step"), (2, "Arpeggi")].map(|(n, track)| {
+            let mut url = info(BANDCAMP);
+            url.track_number = Some(n);
+            url.track = track.to_string();
+            url
+        });
+        let album = Album::from(Vec::from(tracks));
+        let order: Vec<&str> = album.songs.iter().map(|song| song.track.as_str()).collect();
+        assert_eq!(order, ["15 Step", "Arpeggi", "House of Cards"]);
+        assert_eq!(album.album_title, "In Rainbows");
+        assert_eq!(album.album_artist, "Radiohead");
+    }
+
+    #[test]
+    fn soundcloud_uploaders_are_artists() {
+        let url = info(SOUNDCLOUD);
+        let extractor = Extractor::of(&url);
+        assert_eq!(extractor, Extractor::SoundCloud);
+        assert_eq!(extractor.album_artist(&url), "Some Producer");
+        // no release date, the upload date is the release
+        assert_eq!(extractor.year(&url).as_deref(), Some("2021"));
+
+        let song = Single::from(info(SOUNDCLOUD));
+        assert_eq!(song.track, "Night Drive");
+        assert_eq!(song.artist, "Some Producer");
+        assert_eq!(song.album, "");
+    }
+
+    #[test]
+    fn youtube_titles_are_parsed_and_upload_dates_ignored() {
+        let url = info(YOUTUBE);
+        let extractor = Extractor::of(&url);
+        assert_eq!(extractor, Extractor::YouTube);
+        assert_eq!(extractor.year(&url), None);
+        // a YouTube playlist isn't one artist's album unless yt-dlp says so
+        assert_eq!(extractor.album_artist(&url), "");
+
+        let song = Single::from(info(YOUTUBE));
+        assert_eq!(song.artist, "Rick Astley");
+        assert_eq!(song.track, "Never Gonna Give You Up (Official Music Video)");
+        assert!(song.inferred.track && song.inferred.artist);
+    }
+
+    #[test]
+    fn youtube_music_topics_give_the_artist() {
+        let url = info(YOUTUBE_MUSIC);
+        assert_eq!(Extractor::of(&url).uploader_artist(&url), "Michael Jackson");
+        assert_eq!(Extractor::of(&url).album_artist(&url), "Michael Jackson");
+
+        let song = Single::from(info(YOUTUBE_MUSIC));
+        assert_eq!(song.track, "Billie Jean");
+        assert_eq!(song.album, "Thriller");
+        assert_eq!(song.year, "1982");
+        assert!(!song.inferred.track && !song.inferred.artist);
+    }
+}
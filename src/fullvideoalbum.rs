@@ -1,35 +1,77 @@
 use eframe::egui;
-use std::path::PathBuf;
-use std::process::Command;
-use crate::{UrlInfo, Single, Chapter, Renderable, Downloadable};
-use crate::utils;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use crate::{UrlInfo, Single, Chapter, Renderable, Downloadable, Appendable};
+use crate::single;
+use crate::metadata::Lookup;
+use crate::utils::{self, Task};
+use crate::preview::{self, CoverPreview};
+use crate::settings::Settings;
+use crate::cover;
+use crate::extractor::Extractor;
+use crate::layout;
+use crate::loudness;
+use crate::trim;
+use crate::sponsorblock;
 
 extern crate sanitize_filename;
 
 #[derive(Default)]
 pub struct FullVideoAlbum {
     pub webpage_url: String,
+    pub video_id: String,
+    pub uploader: String,
     pub description: String,
+    pub thumbnail: String,
     pub use_thumbnail: bool,
     pub cover_path: Option<PathBuf>,
+    pub preview: CoverPreview,
+    pub show_cover_dialog: bool,
 
     pub album_title: String,
     pub album_artist: String,
     pub songs: Vec<Single>,
+    pub renumber: bool,
     pub chapters: Vec<Chapter>,
+    pub lookup: Lookup,
+
+    /// Source video of each disc, disc 1 is the fetched video.
+    pub discs: Vec<DiscSource>,
+    pub disc_url: String,
+    pub disc_status: String,
+    pub disc_fetch: Option<Task<Vec<UrlInfo>>>,
+    pub disc_folders: bool,
 
     pub marked: Option<usize>
 }
 
+pub struct DiscSource {
+    pub webpage_url: String,
+    pub video_id: String,
+    pub thumbnail: String
+}
+
 impl From<UrlInfo> for FullVideoAlbum {
     fn from(url: UrlInfo) -> Self {
+        let album_artist = Extractor::of(&url).uploader_artist(&url);
         let mut fv_album = FullVideoAlbum::default();
         fv_album.webpage_url = url.webpage_url;
+        fv_album.video_id = url.id;
+        fv_album.uploader = if url.uploader.is_empty() { url.channel } else { url.uploader };
         fv_album.description = url.description;
         fv_album.use_thumbnail = true;
+        fv_album.thumbnail = url.thumbnail;
+        fv_album.renumber = true;
 
         fv_album.album_title = url.title;
-        fv_album.album_artist = url.uploader;
+        fv_album.album_artist = album_artist;
+        fv_album.lookup.title = fv_album.album_title.to_string();
+        fv_album.lookup.artist = fv_album.album_artist.to_string();
+        fv_album.discs = vec![DiscSource {
+            webpage_url: fv_album.webpage_url.to_string(),
+            video_id: fv_album.video_id.to_string(),
+            thumbnail: fv_album.thumbnail.to_string()
+        }];
 
         if let Some(chapters) = url.chapters {
             fv_album.chapters = chapters;
@@ -40,7 +82,9 @@ impl From<UrlInfo> for FullVideoAlbum {
         fv_album.songs = Vec::new();
         for chapter in &fv_album.chapters {
             let mut song = Single::default();
+            song.inherit_source(&fv_album.webpage_url, &fv_album.video_id, &fv_album.uploader);
             song.use_thumbnail = true;
+            song.thumbnail = fv_album.thumbnail.to_string();
             song.track = chapter.title.to_string();
             song.artist = fv_album.album_artist.clone();
             fv_album.songs.push(song);
@@ -50,8 +94,18 @@ impl From<UrlInfo> for FullVideoAlbum {
     }
 }
 
+impl Appendable for FullVideoAlbum {
+    /// Each video becomes another disc.
+    fn append(&mut self, urls: Vec<UrlInfo>, _split_chapters: bool) -> bool {
+        for url in urls {
+            self.push_disc(url);
+        }
+        return true
+    }
+}
+
 impl Renderable for FullVideoAlbum {
-    fn render(&mut self, ui: &mut egui::Ui) {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
         ui.horizontal(|ui| {
             ui.label("Album Title");
             ui.text_edit_singleline(&mut self.album_title);
@@ -70,7 +124,17 @@ impl Renderable for FullVideoAlbum {
                 }
             }
         });
+        if let Some(release) = self.lookup.render_release(ui, &self.webpage_url) {
+            self.album_title = release.title.to_string();
+            self.album_artist = release.artist.to_string();
+            single::apply_release(&mut self.songs, &release, false);
+        }
         ui.horizontal(|ui| {
+            let source = CoverPreview::source_for(self.use_thumbnail, &self.cover_path, &self.thumbnail);
+            if self.preview.show(ui, &source, 96.0).clicked() {
+                self.show_cover_dialog = true;
+            }
+
             ui.checkbox(&mut self.use_thumbnail, "Use Thumbnail");
 
             if !self.use_thumbnail {
@@ -102,9 +166,44 @@ impl Renderable for FullVideoAlbum {
                 }
             }
         });
+        preview::cover_dialog(
+            ui.ctx(),
+            egui::Id::new("album-cover"),
+            &self.album_title,
+            &mut self.show_cover_dialog,
+            &mut self.preview,
+            &settings.cover,
+            &mut self.use_thumbnail,
+            &mut self.cover_path
+        );
+        single::render_selection(ui, &mut self.songs);
+        ui.checkbox(&mut self.renumber, "Renumber Selected");
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.disc_url);
+            let fetch = ui.add_enabled(self.disc_fetch.is_none(), egui::Button::new(format!("Fetch Disc {}", self.discs.len() + 1)));
+            if fetch.clicked() && !self.disc_url.trim().is_empty() {
+                let disc_url = self.disc_url.to_string();
+                self.disc_fetch = Some(Task::spawn(ui.ctx(), move || crate::fetch_info(&disc_url, false)));
+                self.disc_status = String::default();
+            }
+            ui.checkbox(&mut self.disc_folders, "Disc Folders");
+            self.poll_disc();
+            if self.disc_fetch.is_some() {
+                ui.spinner();
+            }
+            ui.label(&self.disc_status);
+        });
         ui.separator();
+        // discs without chapters aren't part of the album
+        let disc_total = self.songs.iter().map(|song| song.disc).collect::<HashSet<usize>>().len();
         for (i, song) in &mut self.songs.iter_mut().enumerate() {
             // render songs slightly differently
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut song.include, "Include");
+                if disc_total > 1 {
+                    ui.label(format!("Disc {}", song.disc));
+                }
+            });
             ui.horizontal(|ui| {
                 ui.label("Title");
                 ui.text_edit_singleline(&mut song.track);
@@ -117,80 +216,11 @@ impl Renderable for FullVideoAlbum {
                 ui.label("Album");
                 ui.text_edit_singleline(&mut song.album);
             });
-            ui.horizontal(|ui| {
-                ui.checkbox(&mut song.use_thumbnail, "Use Thumbnail");
-
-                if !song.use_thumbnail {
-                    if ui.button("Pick Image").clicked() {
-                        let fd = rfd::FileDialog::new()
-                            .add_filter("image", &["png", "jpg", "jpeg", "webp"]);
-
-                        if let Some(path) = fd.pick_file() {
-                            song.cover_path = Some(path);
-                        }
-                    }
-
-                    if let Some(path) = &song.cover_path {
-                        ui.label("Picked:");
-                        ui.monospace(path.to_string_lossy());
-                    }
-                } else {
-                    song.cover_path = None
-                }
-            });
+            song.render_cover(ui, &settings.cover);
 
             let chapter = self.chapters.get_mut(i).unwrap();
-            ui.add(egui::DragValue::new(&mut chapter.start_time)
-                .clamp_range(0..=((60 * 60 * 24) - 1))
-                .custom_formatter(|n, _| {
-                    let n = n as i32;
-                    let hours = n / (60 * 60);
-                    let mins = (n / 60) % 60;
-                    let secs = n % 60;
-                    return format!("{hours:02}:{mins:02}:{secs:02}")
-                })
-                .custom_parser(|s| {
-                    let parts: Vec<&str> = s.split(':').collect();
-                    if parts.len() == 3 {
-                        parts[0].parse::<i32>().and_then(|h| {
-                            parts[1].parse::<i32>().and_then(|m| {
-                                parts[2].parse::<i32>().map(|s| {
-                                    return ((h * 60 * 60) + (m * 60) + s) as f64
-                                })
-                            })
-                        })
-                        .ok()
-                    } else {
-                        return None
-                    }
-                })
-            );
-
-            ui.add(egui::DragValue::new(&mut chapter.end_time)
-                .clamp_range(0..=((60 * 60 * 24) - 1))
-                .custom_formatter(|n, _| {
-                    let n = n as i32;
-                    let hours = n / (60 * 60);
-                    let mins = (n / 60) % 60;
-                    let secs = n % 60;
-                    return format!("{hours:02}:{mins:02}:{secs:02}")
-                })
-                .custom_parser(|s| {
-                    let parts: Vec<&str> = s.split(':').collect();
-                    if parts.len() == 3 {
-                        parts[0].parse::<i32>().and_then(|h| {
-                            parts[1].parse::<i32>().and_then(|m| {
-                                parts[2].parse::<i32>().map(|s| {
-                                    return ((h * 60 * 60) + (m * 60) + s) as f64
-                                })
-                            })
-                        })
-                        .ok()
-                    } else {
-                        return None
-                    }
-                })
-            );
+            ui.add(utils::time_edit(&mut chapter.start_time));
+            ui.add(utils::time_edit(&mut chapter.end_time));
 
             if ui.button("Remove Chapter").clicked() {
                 self.marked = Some(i);
@@ -210,47 +240,76 @@ impl Renderable for FullVideoAlbum {
 }
 
 impl Downloadable for FullVideoAlbum {
-    fn download(&self, base_dir: &PathBuf) {
-        let download_dir = base_dir.join("albums").join(sanitize_filename::sanitize(&self.album_title));
-        let output_format = format!("{}---FULL.%(ext)s", sanitize_filename::sanitize(&self.album_title));
-        utils::download_video(&self.webpage_url, &output_format, download_dir.to_str().unwrap(), self.use_thumbnail);
-
-        let full_mp3_name = output_format.replace("%(ext)s", "mp3");
-        let full_mp3_path = download_dir.join(full_mp3_name);
-        for (i, song) in self.songs.iter().enumerate() {
-            let mp3_name = format!("{}---{}.mp3", sanitize_filename::sanitize(&song.track), sanitize_filename::sanitize(&song.artist));
-            let song_mp3_path = download_dir.join(mp3_name);
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        let mut errors = Vec::new();
+        let flat_dir = base_dir.join("albums").join(utils::sanitize_or(&self.album_title, "Album"));
+        let job = utils::Job::new(base_dir);
+        // full videos of the discs, downloaded once the first of their songs comes up
+        let mut full_videos: HashMap<usize, Option<(String, PathBuf, Vec<sponsorblock::Segment>)>> = HashMap::new();
+        let mut taken = HashSet::new();
+        let mut album_cover = None;
+        let mut kept = Vec::new();
+        let positions = single::album_positions(&self.songs, self.renumber);
+        for (i, (song, position)) in self.songs.iter().zip(positions).enumerate() {
+            let position = match position {
+                Some(position) => position,
+                None => continue
+            };
+            let full_video = full_videos.entry(song.disc)
+                .or_insert_with(|| self.download_disc(song.disc, &job.dir, settings, &mut errors));
+            let (full_stem, full_mp3_path, segments) = match full_video {
+                Some(full_video) => full_video,
+                None => continue
+            };
+
+            let stem = utils::unique_stem(utils::song_stem(&song.track, &song.artist), &mut taken);
+            let mp3_name = format!("{}.mp3", stem);
+            let song_mp3_path = job.dir.join(mp3_name);
 
             let chapter = self.chapters.get(i).unwrap();
-            Command::new("ffmpeg")
-                .args(["-ss", &chapter.start_time.to_string()])
-                .args(["-to", &chapter.end_time.to_string()])
-                .args(["-i", &full_mp3_path.to_str().unwrap()])
-                .args(["-c", "copy"])
-                .arg(&song_mp3_path)
-                .output()
-                .expect("ffmpeg split failed");
-
-            if let Some(path) = &song.cover_path {
-                let cover_name = path.file_name().unwrap();
-                let new_path = &download_dir.join(cover_name);
-                let _ = std::fs::copy(path, new_path);
-
-                let cover_path = utils::convert_jpg(&new_path);
-                song.tag(&song_mp3_path, &cover_path, Some(i + 1));
-            } else if song.use_thumbnail {
-                let cover_name = output_format.replace("%(ext)s", "webp");
-                let cover_path = &download_dir.join(cover_name);
-                let cover_path = utils::convert_jpg(&cover_path);
-                song.tag(&song_mp3_path, &cover_path, Some(i + 1));
+            if let Err(e) = trim::extract(full_mp3_path, &song_mp3_path, chapter.start_time, chapter.end_time) {
+                errors.push(song.problem(e));
+                continue;
+            }
+            let steps = [
+                sponsorblock::remove(&song_mp3_path, segments, chapter.start_time),
+                loudness::normalize(&song_mp3_path, &settings.loudness)
+            ];
+            errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| song.problem(e)));
+
+            let cover_path = song.prepare_cover(&job.dir, full_stem, &settings.cover).unwrap_or_else(|e| {
+                errors.push(song.problem(e));
+                None
+            });
+            if let Err(e) = song.tag(&song_mp3_path, cover_path.as_ref(), Some(position), Some(&self.album_artist)) {
+                errors.push(song.problem(e));
+            }
+            let dest = layout::album_song_path(settings.layout, base_dir, &flat_dir, &stem, song.place(&self.album_artist, Some(position)), self.disc_folders);
+            match job.keep(&song_mp3_path, &dest) {
+                Ok(song_mp3_path) => kept.push(song_mp3_path),
+                Err(e) => errors.push(song.problem(e))
+            }
+
+            if album_cover.is_none() {
+                album_cover = cover_path;
             }
         }
 
-        utils::cleanup(&download_dir);
+        errors.extend(loudness::tag_replaygain(&kept, true, &settings.loudness));
+
+        // the folder the album ended up in, which depends on the layout
+        let album_dir = kept.first().and_then(|path| layout::album_dir(path, self.disc_folders));
+        if let (Some(album_cover), Some(album_dir)) = (&album_cover, album_dir) {
+            if let Err(e) = cover::write_folder_art(album_cover, album_dir, settings.folder_art, settings.cover.jpeg_quality) {
+                errors.push(e);
+            }
+        }
+        return errors
     }
 }
 
 impl FullVideoAlbum {
+    /// Adds a chapter to the last disc.
     fn add_chapter(&mut self) {
         self.chapters.push(Chapter {
             start_time: 0.0,
@@ -258,12 +317,78 @@ impl FullVideoAlbum {
             title: "New Chapter".to_string()
         });
 
+        let disc = self.discs.len().max(1);
         let mut song = Single::default();
+        if let Some(source) = self.discs.last() {
+            song.inherit_source(&source.webpage_url, &source.video_id, &self.uploader);
+            song.thumbnail = source.thumbnail.to_string();
+        }
         song.artist = self.album_artist.to_string();
         song.album = self.album_title.to_string();
+        song.disc = disc;
         self.songs.push(song);
     }
 
+    /// Adds the fetched `disc_url` as the next disc once it comes in.
+    fn poll_disc(&mut self) {
+        let urls = match self.disc_fetch.as_ref().and_then(|task| task.poll()) {
+            Some(urls) => urls,
+            None => return
+        };
+        self.disc_fetch = None;
+
+        match urls {
+            Ok(urls) => {
+                for url in urls {
+                    self.push_disc(url);
+                }
+                self.disc_url = String::default();
+            },
+            Err(e) => self.disc_status = e
+        }
+    }
+
+    /// Adds a video as the next disc, its chapters become songs on that disc.
+    fn push_disc(&mut self, url: UrlInfo) {
+        self.discs.push(DiscSource { webpage_url: url.webpage_url, video_id: url.id, thumbnail: url.thumbnail });
+        let disc = self.discs.len();
+        let source = self.discs.last().unwrap();
+
+        for chapter in url.chapters.unwrap_or_default() {
+            let mut song = Single::default();
+            song.inherit_source(&source.webpage_url, &source.video_id, &self.uploader);
+            song.use_thumbnail = self.use_thumbnail;
+            song.thumbnail = source.thumbnail.to_string();
+            song.track = chapter.title.to_string();
+            song.artist = self.album_artist.to_string();
+            song.album = self.album_title.to_string();
+            song.disc = disc;
+            self.songs.push(song);
+            self.chapters.push(chapter);
+        }
+    }
+
+    /// Downloads the full video of `disc` and looks up its segments.
+    /// Returns the file's stem, for finding its thumbnail, its path and the segments, or `None` if the download failed.
+    fn download_disc(&self, disc: usize, dir: &Path, settings: &Settings, errors: &mut Vec<String>) -> Option<(String, PathBuf, Vec<sponsorblock::Segment>)> {
+        let source = &self.discs[disc - 1];
+        let full_stem = format!("{}---FULL-{}", utils::sanitize_or(&self.album_title, "Album"), disc);
+        let output_format = format!("{}.%(ext)s", full_stem);
+        // the songs' own choice, the album's is only applied to them with its Apply button
+        let use_thumbnail = self.songs.iter().any(|song| song.disc == disc && song.include && song.use_thumbnail);
+        if let Err(e) = utils::download_video(&source.webpage_url, &output_format, dir, use_thumbnail, false, &[]) {
+            errors.push(format!("Disc {}: {}", disc, e));
+            return None
+        }
+
+        let full_mp3_path = dir.join(output_format.replace("%(ext)s", "mp3"));
+        let segments = sponsorblock::segments(&source.video_id, &settings.sponsorblock).unwrap_or_else(|e| {
+            errors.push(format!("Disc {} segments: {}", disc, e));
+            Vec::new()
+        });
+        return Some((full_stem, full_mp3_path, segments))
+    }
+
     fn remove_marked(&mut self) {
         if let Some(i) = self.marked {
             self.chapters.remove(i);
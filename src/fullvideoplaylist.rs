@@ -1,17 +1,25 @@
 use eframe::egui;
+use std::collections::HashSet;
 use std::path::PathBuf;
-use std::process::Command;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use crate::{UrlInfo, Single, Chapter, Renderable, Downloadable};
+use crate::{UrlInfo, Single, Chapter, Renderable, Downloadable, Appendable};
+use crate::single;
 use crate::utils;
+use crate::loudness;
+use crate::trim;
+use crate::sponsorblock;
+use crate::settings::Settings;
 
 extern crate sanitize_filename;
 
 #[derive(Default)]
 pub struct FullVideoPlaylist {
     pub webpage_url: String,
+    pub video_id: String,
+    pub uploader: String,
     pub description: String,
+    pub thumbnail: String,
     pub use_thumbnail: bool,
     pub cover_path: Option<PathBuf>,
 
@@ -26,8 +34,11 @@ impl From<UrlInfo> for FullVideoPlaylist {
     fn from(url: UrlInfo) -> Self {
         let mut fv_playlist = FullVideoPlaylist::default();
         fv_playlist.webpage_url = url.webpage_url;
+        fv_playlist.video_id = url.id;
+        fv_playlist.uploader = if url.uploader.is_empty() { url.channel } else { url.uploader };
         fv_playlist.description = url.description;
         fv_playlist.use_thumbnail = true;
+        fv_playlist.thumbnail = url.thumbnail;
 
         fv_playlist.playlist_title = url.title;
 
@@ -40,7 +51,9 @@ impl From<UrlInfo> for FullVideoPlaylist {
         fv_playlist.songs = Vec::new();
         for chapter in &fv_playlist.chapters {
             let mut song = Single::default();
+            song.inherit_source(&fv_playlist.webpage_url, &fv_playlist.video_id, &fv_playlist.uploader);
             song.use_thumbnail = true;
+            song.thumbnail = fv_playlist.thumbnail.to_string();
             song.track = chapter.title.to_string();
             fv_playlist.songs.push(song);
         }
@@ -49,8 +62,10 @@ impl From<UrlInfo> for FullVideoPlaylist {
     }
 }
 
+impl Appendable for FullVideoPlaylist {}
+
 impl Renderable for FullVideoPlaylist {
-    fn render(&mut self, ui: &mut egui::Ui) {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
         ui.horizontal(|ui| {
             ui.label("Playlist Title");
             ui.text_edit_singleline(&mut self.playlist_title);
@@ -87,9 +102,11 @@ impl Renderable for FullVideoPlaylist {
                 }
             }
         });
+        single::render_selection(ui, &mut self.songs);
         ui.separator();
         for (i, song) in &mut self.songs.iter_mut().enumerate() {
             // render songs slightly differently
+            ui.checkbox(&mut song.include, "Include");
             ui.horizontal(|ui| {
                 ui.label("Title");
                 ui.text_edit_singleline(&mut song.track);
@@ -102,80 +119,11 @@ impl Renderable for FullVideoPlaylist {
                 ui.label("Album");
                 ui.text_edit_singleline(&mut song.album);
             });
-            ui.horizontal(|ui| {
-                ui.checkbox(&mut song.use_thumbnail, "Use Thumbnail");
-
-                if !song.use_thumbnail {
-                    if ui.button("Pick Image").clicked() {
-                        let fd = rfd::FileDialog::new()
-                            .add_filter("image", &["png", "jpg", "jpeg", "webp"]);
-
-                        if let Some(path) = fd.pick_file() {
-                            song.cover_path = Some(path);
-                        }
-                    }
-
-                    if let Some(path) = &song.cover_path {
-                        ui.label("Picked:");
-                        ui.monospace(path.to_string_lossy());
-                    }
-                } else {
-                    song.cover_path = None
-                }
-            });
+            song.render_cover(ui, &settings.cover);
 
             let chapter = self.chapters.get_mut(i).unwrap();
-            ui.add(egui::DragValue::new(&mut chapter.start_time)
-                .clamp_range(0..=((60 * 60 * 24) - 1))
-                .custom_formatter(|n, _| {
-                    let n = n as i32;
-                    let hours = n / (60 * 60);
-                    let mins = (n / 60) % 60;
-                    let secs = n % 60;
-                    return format!("{hours:02}:{mins:02}:{secs:02}")
-                })
-                .custom_parser(|s| {
-                    let parts: Vec<&str> = s.split(':').collect();
-                    if parts.len() == 3 {
-                        parts[0].parse::<i32>().and_then(|h| {
-                            parts[1].parse::<i32>().and_then(|m| {
-                                parts[2].parse::<i32>().map(|s| {
-                                    return ((h * 60 * 60) + (m * 60) + s) as f64
-                                })
-                            })
-                        })
-                        .ok()
-                    } else {
-                        return None
-                    }
-                })
-            );
-
-            ui.add(egui::DragValue::new(&mut chapter.end_time)
-                .clamp_range(0..=((60 * 60 * 24) - 1))
-                .custom_formatter(|n, _| {
-                    let n = n as i32;
-                    let hours = n / (60 * 60);
-                    let mins = (n / 60) % 60;
-                    let secs = n % 60;
-                    return format!("{hours:02}:{mins:02}:{secs:02}")
-                })
-                .custom_parser(|s| {
-                    let parts: Vec<&str> = s.split(':').collect();
-                    if parts.len() == 3 {
-                        parts[0].parse::<i32>().and_then(|h| {
-                            parts[1].parse::<i32>().and_then(|m| {
-                                parts[2].parse::<i32>().map(|s| {
-                                    return ((h * 60 * 60) + (m * 60) + s) as f64
-                                })
-                            })
-                        })
-                        .ok()
-                    } else {
-                        return None
-                    }
-                })
-            );
+            ui.add(utils::time_edit(&mut chapter.start_time));
+            ui.add(utils::time_edit(&mut chapter.end_time));
 
             if ui.button("Remove Chapter").clicked() {
                 self.marked = Some(i);
@@ -195,51 +143,77 @@ impl Renderable for FullVideoPlaylist {
 }
 
 impl Downloadable for FullVideoPlaylist {
-    fn download(&self, base_dir: &PathBuf) {
-        let download_dir = base_dir.join("playlists").join(sanitize_filename::sanitize(&self.playlist_title));
-        let output_format = format!("{}---FULL.%(ext)s", sanitize_filename::sanitize(&self.playlist_title));
-        utils::download_video(&self.webpage_url, &output_format, download_dir.to_str().unwrap(), self.use_thumbnail);
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        let mut errors = Vec::new();
+        let playlist_dir = base_dir.join("playlists").join(utils::sanitize_or(&self.playlist_title, "Playlist"));
+        if let Err(e) = fs::create_dir_all(&playlist_dir) {
+            errors.push(format!("{}: {}", playlist_dir.display(), e));
+            return errors
+        }
+        let job = utils::Job::new(base_dir);
+        let full_stem = format!("{}---FULL", utils::sanitize_or(&self.playlist_title, "Playlist"));
+        let output_format = format!("{}.%(ext)s", full_stem);
+        if let Err(e) = utils::download_video(&self.webpage_url, &output_format, &job.dir, self.use_thumbnail, false, &[]) {
+            errors.push(e);
+            return errors
+        }
 
         let mut file_string = String::default();
+        let mut kept = Vec::new();
 
         let full_mp3_name = output_format.replace("%(ext)s", "mp3");
-        let full_mp3_path = download_dir.join(full_mp3_name);
+        let full_mp3_path = job.dir.join(full_mp3_name);
+        let segments = sponsorblock::segments(&self.video_id, &settings.sponsorblock).unwrap_or_else(|e| {
+            errors.push(format!("segments: {}", e));
+            Vec::new()
+        });
+        let mut taken = HashSet::new();
         for (i, song) in self.songs.iter().enumerate() {
-            let mp3_name = format!("{}---{}.mp3", sanitize_filename::sanitize(&song.track), sanitize_filename::sanitize(&song.artist));
-            let song_mp3_path = download_dir.join(mp3_name);
+            if !song.include {
+                continue;
+            }
 
-            file_string.push_str(song_mp3_path.to_str().unwrap());
-            file_string.push('\n');
+            let stem = utils::unique_stem(utils::song_stem(&song.track, &song.artist), &mut taken);
+            let mp3_name = format!("{}.mp3", stem);
+            let song_mp3_path = job.dir.join(mp3_name);
 
             let chapter = self.chapters.get(i).unwrap();
-            Command::new("ffmpeg")
-                .args(["-ss", &chapter.start_time.to_string()])
-                .args(["-to", &chapter.end_time.to_string()])
-                .args(["-i", &full_mp3_path.to_str().unwrap()])
-                .args(["-c", "copy"])
-                .arg(&song_mp3_path)
-                .output()
-                .expect("ffmpeg split failed");
-
-            if let Some(path) = &song.cover_path {
-                let cover_name = path.file_name().unwrap();
-                let new_path = &download_dir.join(cover_name);
-                let _ = std::fs::copy(path, new_path);
-
-                let cover_path = utils::convert_jpg(&new_path);
-                song.tag(&song_mp3_path, &cover_path, None);
-            } else if song.use_thumbnail {
-                let cover_name = output_format.replace("%(ext)s", "webp");
-                let cover_path = &download_dir.join(cover_name);
-                let cover_path = utils::convert_jpg(&cover_path);
-                song.tag(&song_mp3_path, &cover_path, None);
+            if let Err(e) = trim::extract(&full_mp3_path, &song_mp3_path, chapter.start_time, chapter.end_time) {
+                errors.push(song.problem(e));
+                continue;
+            }
+            let steps = [
+                sponsorblock::remove(&song_mp3_path, &segments, chapter.start_time),
+                loudness::normalize(&song_mp3_path, &settings.loudness)
+            ];
+            errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| song.problem(e)));
+
+            let cover_path = song.prepare_cover(&job.dir, &full_stem, &settings.cover).unwrap_or_else(|e| {
+                errors.push(song.problem(e));
+                None
+            });
+            if let Err(e) = song.tag(&song_mp3_path, cover_path.as_ref(), None, None) {
+                errors.push(song.problem(e));
             }
+            let dest = settings.layout.song_path(base_dir, &playlist_dir, &stem, &song.place("", None));
+            let song_mp3_path = match job.keep(&song_mp3_path, &dest) {
+                Ok(song_mp3_path) => song_mp3_path,
+                Err(e) => {
+                    errors.push(song.problem(e));
+                    continue;
+                }
+            };
+            file_string.push_str(song_mp3_path.to_str().unwrap());
+            file_string.push('\n');
+            kept.push(song_mp3_path);
         }
+        errors.extend(loudness::tag_replaygain(&kept, false, &settings.loudness));
 
-        let file_path = download_dir.join(format!("{}.m3u8", sanitize_filename::sanitize(&self.playlist_title)));
-        let mut file = File::create(file_path).unwrap();
-        let _ = file.write_all(file_string.as_bytes());
-        utils::cleanup(&download_dir);
+        let file_path = playlist_dir.join(format!("{}.m3u8", utils::sanitize_or(&self.playlist_title, "Playlist")));
+        if let Err(e) = File::create(&file_path).and_then(|mut file| file.write_all(file_string.as_bytes())) {
+            errors.push(format!("{}: {}", file_path.display(), e));
+        }
+        return errors
     }
 }
 
@@ -251,7 +225,9 @@ impl FullVideoPlaylist {
             title: "New Chapter".to_string()
         });
 
-        let song = Single::default();
+        let mut song = Single::default();
+        song.inherit_source(&self.webpage_url, &self.video_id, &self.uploader);
+        song.thumbnail = self.thumbnail.to_string();
         self.songs.push(song);
     }
 
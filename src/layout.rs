@@ -0,0 +1,332 @@
+use eframe::egui;
+use id3::{Tag, TagLike};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::cover::FOLDER_ART_NAMES;
+use crate::duplicates;
+use crate::library;
+use crate::utils;
+
+/// Where downloaded songs are stored under the download directory.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum Layout {
+    /// `singles/`, `albums/<album>/` and `playlists/<playlist>/`, songs named `track---artist`.
+    #[default]
+    Flat,
+    /// `<artist>/<album>/NN track`
+    ArtistAlbum,
+    /// `<album artist>/<year> - <album>/NN track`
+    AlbumArtistYear
+}
+
+/// What a song's path is built from.
+pub struct Place<'a> {
+    pub artist: &'a str,
+    pub album_artist: &'a str,
+    pub album: &'a str,
+    pub year: &'a str,
+    pub track: &'a str,
+    pub track_no: Option<usize>,
+    /// Only set for multi-disc albums without disc folders, it goes in the file name then.
+    pub disc: Option<usize>
+}
+
+impl Layout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Flat => "Flat",
+            Layout::ArtistAlbum => "Artist/Album",
+            Layout::AlbumArtistYear => "Album Artist/Year - Album"
+        }
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Library Layout")
+            .selected_text(self.name())
+            .show_ui(ui, |ui| {
+                for layout in [Layout::Flat, Layout::ArtistAlbum, Layout::AlbumArtistYear] {
+                    ui.selectable_value(self, layout, layout.name());
+                }
+            });
+    }
+
+    /// Final path of a song. `flat_dir` and `flat_stem` are where the flat layout puts it,
+    /// the other layouts only go by the song's tags.
+    pub fn song_path(&self, base_dir: &Path, flat_dir: &Path, flat_stem: &str, place: &Place) -> PathBuf {
+        let file_name = match (place.disc, place.track_no) {
+            (Some(disc), Some(n)) => format!("{}-{:02} {}.mp3", disc, n, utils::sanitize_or(place.track, "Untitled")),
+            (None, Some(n)) => format!("{:02} {}.mp3", n, utils::sanitize_or(place.track, "Untitled")),
+            (_, None) => format!("{}.mp3", utils::sanitize_or(place.track, "Untitled"))
+        };
+
+        match self {
+            Layout::Flat => flat_dir.join(format!("{}.mp3", flat_stem)),
+            Layout::ArtistAlbum => base_dir
+                .join(utils::sanitize_or(place.artist, "Unknown Artist"))
+                .join(utils::sanitize_or(place.album, "Singles"))
+                .join(file_name),
+            Layout::AlbumArtistYear => {
+                let artist = if place.album_artist.trim().is_empty() { place.artist } else { place.album_artist };
+                let album = utils::sanitize_or(place.album, "Singles");
+                let album = if place.year.trim().is_empty() {
+                    album
+                } else {
+                    utils::sanitize_or(&format!("{} - {}", place.year.trim(), album), "Singles")
+                };
+                base_dir
+                    .join(utils::sanitize_or(artist, "Unknown Artist"))
+                    .join(album)
+                    .join(file_name)
+            }
+        }
+    }
+}
+
+/// Moves every song outside `playlists/` to where `layout` puts it, going by its tags.
+/// Playlist files are updated to the new paths, folder art follows its album and
+/// folders left empty are removed. Symlinks and songs without a readable tag stay
+/// where they are. Returns how many songs moved.
+pub fn migrate(base_dir: &Path, layout: Layout) -> Result<usize, String> {
+    let playlists_dir = base_dir.join("playlists");
+    let mut moves: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut errors = Vec::new();
+
+    for path in library::mp3_paths(base_dir) {
+        if path.starts_with(&playlists_dir) {
+            continue;
+        }
+        // links made for duplicates point at an absolute path, they'd dangle once their target moves
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            continue;
+        }
+        let tag = match Tag::read_from_path(&path) {
+            Ok(tag) => tag,
+            Err(e) => {
+                errors.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        let full_artist = tag.artist().unwrap_or_default();
+        let artist = full_artist.split(" feat. ").next().unwrap_or_default();
+        let track = tag.title().unwrap_or_default();
+        let album = tag.album().unwrap_or_default();
+        let track_no = tag.track().map(|n| n as usize);
+        let place = Place {
+            artist,
+            album_artist: tag.album_artist().unwrap_or_default(),
+            album,
+            year: &tag.year().map(|year| year.to_string()).unwrap_or_default(),
+            track,
+            track_no,
+            disc: tag.disc().filter(|_| tag.total_discs().unwrap_or_default() > 1).map(|disc| disc as usize)
+        };
+
+        let flat_dir = match track_no {
+            Some(_) if !album.is_empty() => base_dir.join("albums").join(utils::sanitize_or(album, "Album")),
+            _ => base_dir.join("singles")
+        };
+        let dest = free_dest(&path, &layout.song_path(base_dir, &flat_dir, &utils::song_stem(track, artist), &place));
+        if dest == path {
+            continue;
+        }
+
+        if let Err(e) = move_file(&path, &dest) {
+            errors.push(e);
+            continue;
+        }
+        moves.insert(path, dest);
+    }
+
+    for playlist_dir in fs::read_dir(&playlists_dir).into_iter().flatten().filter_map(|entry| entry.ok()) {
+        for playlist in duplicates::m3u8_files(&playlist_dir.path()) {
+            let contents = match fs::read_to_string(&playlist) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    errors.push(e.to_string());
+                    continue;
+                }
+            };
+            let mut updated = String::default();
+            for line in contents.lines() {
+                match moves.get(Path::new(line)) {
+                    Some(to) => updated.push_str(&to.to_string_lossy()),
+                    None => updated.push_str(line)
+                }
+                updated.push('\n');
+            }
+            if let Err(e) = fs::write(&playlist, updated) {
+                errors.push(e.to_string());
+            }
+        }
+    }
+
+    for (from, to) in &moves {
+        let (old_dir, new_dir) = match (from.parent(), to.parent()) {
+            (Some(old_dir), Some(new_dir)) => (old_dir, new_dir),
+            _ => continue
+        };
+        if !library::mp3_paths(old_dir).is_empty() {
+            continue;
+        }
+        for name in FOLDER_ART_NAMES {
+            if old_dir.join(name).exists() && !new_dir.join(name).exists() {
+                let _ = move_file(&old_dir.join(name), &new_dir.join(name));
+            }
+        }
+        // only removes folders that are empty now, the artist folder too for nested layouts
+        let _ = fs::remove_dir(old_dir);
+        if let Some(parent) = old_dir.parent().filter(|parent| *parent != base_dir) {
+            let _ = fs::remove_dir(parent);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(moves.len())
+    } else {
+        Err(format!("moved {}, {}", moves.len(), errors.join(", ")))
+    }
+}
+
+/// Whether `path` is in a folder `layout` puts an album's songs in, rather than singles or playlists.
+pub fn in_album_folder(base_dir: &Path, layout: Layout, path: &Path) -> bool {
+    let folder = match path.parent() {
+        Some(folder) => folder,
+        None => return false
+    };
+    match layout {
+        Layout::Flat => path.starts_with(base_dir.join("albums")),
+        Layout::ArtistAlbum | Layout::AlbumArtistYear => {
+            // disc folders sit inside the album folder
+            let is_disc = folder.file_name().is_some_and(|name| name.to_string_lossy().starts_with("Disc "));
+            let album_dir = if is_disc { folder.parent().unwrap_or(folder) } else { folder };
+            let name = album_dir.file_name().unwrap_or_default().to_string_lossy();
+            !path.starts_with(base_dir.join("playlists")) && name != "Singles" && !name.ends_with(" - Singles")
+        }
+    }
+}
+
+/// Path of an album's song. With `disc_folders` the songs of a multi-disc album go in
+/// `Disc N` folders inside the album folder instead of having the disc in their name.
+pub fn album_song_path(layout: Layout, base_dir: &Path, flat_dir: &Path, flat_stem: &str, mut place: Place, disc_folders: bool) -> PathBuf {
+    match place.disc.filter(|_| disc_folders) {
+        Some(disc) => {
+            place.disc = None;
+            let disc_dir = format!("Disc {}", disc);
+            let dest = layout.song_path(base_dir, &flat_dir.join(&disc_dir), flat_stem, &place);
+            if layout == Layout::Flat {
+                return dest
+            }
+            let album_dir = dest.parent().unwrap_or(base_dir);
+            return album_dir.join(disc_dir).join(dest.file_name().unwrap_or_default())
+        },
+        None => layout.song_path(base_dir, flat_dir, flat_stem, &place)
+    }
+}
+
+/// Album folder of a song placed by [`album_song_path`], above its `Disc N` folder if it has one.
+pub fn album_dir(song_path: &Path, disc_folders: bool) -> Option<&Path> {
+    let parent = song_path.parent()?;
+    let in_disc_folder = parent.file_name().is_some_and(|name| name.to_string_lossy().starts_with("Disc "));
+    if disc_folders && in_disc_folder {
+        return parent.parent()
+    }
+    return Some(parent)
+}
+
+/// `dest`, or a free `stem (n)` next to it if another file is there already.
+/// `from` doesn't count as taken, so songs that got a free name before stay where they are.
+fn free_dest(from: &Path, dest: &Path) -> PathBuf {
+    if !dest.exists() || from == dest {
+        return dest.to_path_buf()
+    }
+
+    let dir = dest.parent().unwrap_or(Path::new(""));
+    let mut taken = utils::existing_stems(dir);
+    if from.parent() == Some(dir) {
+        if let Some(stem) = from.file_stem() {
+            taken.remove(&stem.to_string_lossy().to_lowercase());
+        }
+    }
+    let stem = dest.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    return dir.join(format!("{}.mp3", utils::unique_stem(stem, &mut taken)))
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::rename(from, to).map_err(|e| format!("{}: {}", from.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn place<'a>(album: &'a str, year: &'a str) -> Place<'a> {
+        Place { artist: "Artist", album_artist: "", album, year, track: "Song", track_no: None, disc: None }
+    }
+
+    #[test]
+    fn albumless_songs_go_to_singles() {
+        let base = Path::new("/music");
+        let path = Layout::AlbumArtistYear.song_path(base, base, "", &place("", "2020"));
+        assert_eq!(path, base.join("Artist").join("2020 - Singles").join("Song.mp3"));
+        let path = Layout::AlbumArtistYear.song_path(base, base, "", &place("Album", ""));
+        assert_eq!(path, base.join("Artist").join("Album").join("Song.mp3"));
+        let path = Layout::ArtistAlbum.song_path(base, base, "", &place("", "2020"));
+        assert_eq!(path, base.join("Artist").join("Singles").join("Song.mp3"));
+    }
+
+    #[test]
+    fn taken_destinations_get_free_names() {
+        let dir = std::env::temp_dir().join(format!("bebops-test-free-dest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Song.mp3"), "").unwrap();
+        fs::write(dir.join("Song (2).mp3"), "").unwrap();
+
+        let elsewhere = Path::new("/elsewhere/Song.mp3");
+        assert_eq!(free_dest(elsewhere, &dir.join("Other.mp3")), dir.join("Other.mp3"));
+        assert_eq!(free_dest(elsewhere, &dir.join("Song.mp3")), dir.join("Song (3).mp3"));
+        // already moved here under a free name
+        assert_eq!(free_dest(&dir.join("Song (2).mp3"), &dir.join("Song.mp3")), dir.join("Song (2).mp3"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn album_folders_follow_the_layout() {
+        let base = Path::new("/music");
+        assert!(in_album_folder(base, Layout::Flat, &base.join("albums/Album/Song.mp3")));
+        assert!(!in_album_folder(base, Layout::Flat, &base.join("singles/Song.mp3")));
+        assert!(in_album_folder(base, Layout::ArtistAlbum, &base.join("Artist/Album/01 Song.mp3")));
+        assert!(in_album_folder(base, Layout::ArtistAlbum, &base.join("Artist/Album/Disc 2/01 Song.mp3")));
+        assert!(!in_album_folder(base, Layout::ArtistAlbum, &base.join("Artist/Singles/Song.mp3")));
+        assert!(!in_album_folder(base, Layout::AlbumArtistYear, &base.join("Artist/2020 - Singles/Song.mp3")));
+        assert!(!in_album_folder(base, Layout::AlbumArtistYear, &base.join("playlists/Mix/Song.mp3")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn migrate_leaves_links_and_untagged_songs() {
+        let dir = std::env::temp_dir().join(format!("bebops-test-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("singles")).unwrap();
+        let (song, link, untagged) = (dir.join("singles/Song---Artist.mp3"), dir.join("singles/Link.mp3"), dir.join("singles/Untagged.mp3"));
+        fs::write(&song, "").unwrap();
+        let mut tag = Tag::new();
+        tag.set_artist("Artist");
+        tag.set_title("Song");
+        tag.write_to_path(&song, id3::Version::Id3v24).unwrap();
+        std::os::unix::fs::symlink(&song, &link).unwrap();
+        fs::write(&untagged, "").unwrap();
+
+        let result = migrate(&dir, Layout::ArtistAlbum);
+        assert!(result.is_err_and(|e| e.starts_with("moved 1,") && e.contains("Untagged.mp3")));
+        assert!(dir.join("Artist/Singles/Song.mp3").exists());
+        assert!(fs::symlink_metadata(&link).is_ok());
+        assert!(untagged.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,468 @@
+use eframe::egui;
+use id3::{Tag, TagLike};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::layout::{self, Layout};
+use crate::local;
+use crate::duplicates::{self, DuplicateGroup, Resolution};
+use crate::single::VIDEO_ID;
+use crate::utils::Task;
+
+/// A song in the download folder as its tag describes it.
+pub struct Track {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub year: String,
+    pub track_no: Option<u32>,
+    pub has_cover: bool,
+    pub video_id: String,
+    pub problems: Vec<Problem>
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Problem {
+    NoTag,
+    MissingCover,
+    MissingTrackNumber,
+    Duplicate
+}
+
+impl Problem {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Problem::NoTag => "no tag",
+            Problem::MissingCover => "no cover",
+            Problem::MissingTrackNumber => "no track #",
+            Problem::Duplicate => "duplicate"
+        }
+    }
+}
+
+impl Track {
+    fn read(path: &Path) -> Self {
+        let tag = Tag::read_from_path(path).ok();
+        let text = |value: Option<&str>| value.unwrap_or_default().to_string();
+
+        let mut track = Track {
+            path: path.to_path_buf(),
+            title: text(tag.as_ref().and_then(|tag| tag.title())),
+            artist: text(tag.as_ref().and_then(|tag| tag.artist())),
+            album: text(tag.as_ref().and_then(|tag| tag.album())),
+            year: tag.as_ref().and_then(|tag| tag.year()).map(|year| year.to_string()).unwrap_or_default(),
+            track_no: tag.as_ref().and_then(|tag| tag.track()),
+            has_cover: tag.as_ref().is_some_and(|tag| tag.pictures().next().is_some()),
+            video_id: tag.as_ref()
+                .and_then(|tag| tag.extended_texts().find(|text| text.description == VIDEO_ID).map(|text| text.value.to_string()))
+                .unwrap_or_default(),
+            problems: Vec::new()
+        };
+
+        if tag.is_none() {
+            track.problems.push(Problem::NoTag);
+        }
+        if !track.has_cover {
+            track.problems.push(Problem::MissingCover);
+        }
+        return track
+    }
+
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum LibraryView {
+    #[default]
+    Tracks,
+    Albums,
+    Artists,
+    Duplicates
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Artist,
+    Album,
+    Title,
+    Year,
+    Path
+}
+
+/// Index of everything under the download directory.
+#[derive(Default)]
+pub struct Library {
+    pub tracks: Vec<Track>,
+    pub view: LibraryView,
+    pub sort: SortBy,
+    pub search: String,
+    pub problems_only: bool,
+    pub status: String,
+    /// What the last reorganize or resolve left behind, kept across the rescan after it.
+    pub notice: String,
+    scanning: Option<Task<(Vec<Track>, Vec<DuplicateGroup>)>>,
+
+    pub duplicates: Vec<DuplicateGroup>,
+    /// Also compare the audio itself with `fpcalc`, slow on big libraries.
+    pub fingerprints: bool,
+    pub resolution: Resolution
+}
+
+impl Library {
+    /// Reads the library in the background, `render` picks the result up.
+    pub fn scan(&mut self, ctx: &egui::Context, base_dir: &Path, layout: Layout) {
+        let base_dir = base_dir.to_path_buf();
+        let fingerprints = self.fingerprints;
+        self.scanning = Some(Task::spawn(ctx, move || Ok(scan(&base_dir, layout, fingerprints))));
+        self.status = "Scanning".to_string();
+    }
+
+    fn poll(&mut self) {
+        let scanned = match self.scanning.as_ref().and_then(|task| task.poll()) {
+            Some(scanned) => scanned,
+            None => return
+        };
+        self.scanning = None;
+
+        match scanned {
+            Ok((tracks, duplicates)) => {
+                self.tracks = tracks;
+                self.duplicates = duplicates;
+                let flagged = self.tracks.iter().filter(|track| !track.problems.is_empty()).count();
+                self.status = format!("{} tracks, {} with problems", self.tracks.len(), flagged);
+            },
+            Err(e) => self.status = e
+        }
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scanning.is_some()
+    }
+
+    fn matches(&self, track: &Track) -> bool {
+        if self.problems_only && track.problems.is_empty() {
+            return false
+        }
+        let search = self.search.trim().to_lowercase();
+        search.is_empty()
+            || [&track.title, &track.artist, &track.album].iter().any(|field| field.to_lowercase().contains(&search))
+    }
+
+    fn sorted(&self) -> Vec<&Track> {
+        let mut tracks: Vec<&Track> = self.tracks.iter().filter(|track| self.matches(track)).collect();
+        match self.sort {
+            SortBy::Artist => tracks.sort_by_key(|track| (track.artist.to_lowercase(), track.album.to_lowercase(), track.track_no)),
+            SortBy::Album => tracks.sort_by_key(|track| (track.album.to_lowercase(), track.track_no)),
+            SortBy::Title => tracks.sort_by_key(|track| track.title.to_lowercase()),
+            SortBy::Year => tracks.sort_by_key(|track| (track.year.to_string(), track.album.to_lowercase(), track.track_no)),
+            SortBy::Path => tracks.sort_by_key(|track| track.path.to_path_buf())
+        }
+        return tracks
+    }
+
+    /// Returns files to open for editing when one of the `Edit` buttons is clicked.
+    pub fn render(&mut self, ui: &mut egui::Ui, base_dir: &Path, layout: Layout) -> Option<Vec<PathBuf>> {
+        let mut open = None;
+        self.poll();
+
+        ui.horizontal(|ui| {
+            // the results are replaced once a scan is done, nothing can be changed in the meantime
+            ui.add_enabled_ui(!self.is_scanning(), |ui| {
+                if ui.button("Scan").clicked() {
+                    self.notice = String::default();
+                    self.scan(ui.ctx(), base_dir, layout);
+                }
+                let reorganize = ui.button(format!("Reorganize as {}", layout.name()))
+                    .on_hover_text("Moves every song outside of playlists to where the layout in Settings puts it");
+                if reorganize.clicked() {
+                    self.notice = match layout::migrate(base_dir, layout) {
+                        Ok(moved) => format!("Moved {} songs", moved),
+                        Err(e) => e
+                    };
+                    self.scan(ui.ctx(), base_dir, layout);
+                }
+            });
+            if self.is_scanning() {
+                ui.spinner();
+            }
+            ui.label(&self.status);
+            ui.label(&self.notice);
+        });
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.view, LibraryView::Tracks, "Tracks");
+            ui.selectable_value(&mut self.view, LibraryView::Albums, "Albums");
+            ui.selectable_value(&mut self.view, LibraryView::Artists, "Artists");
+            ui.selectable_value(&mut self.view, LibraryView::Duplicates, format!("Duplicates ({})", self.duplicates.len()));
+            ui.separator();
+            ui.label("Search");
+            ui.text_edit_singleline(&mut self.search);
+            ui.checkbox(&mut self.problems_only, "Problems Only");
+        });
+        ui.separator();
+
+        match self.view {
+            LibraryView::Tracks => self.render_tracks(ui, &mut open),
+            LibraryView::Albums => self.render_albums(ui, &mut open),
+            LibraryView::Artists => self.render_artists(ui),
+            LibraryView::Duplicates => self.render_duplicates(ui, base_dir, layout)
+        }
+        return open
+    }
+
+    fn render_tracks(&mut self, ui: &mut egui::Ui, open: &mut Option<Vec<PathBuf>>) {
+        let mut sort = self.sort;
+        egui::ScrollArea::both().id_source("library-tracks").show(ui, |ui| {
+            egui::Grid::new("library-tracks-grid").striped(true).show(ui, |ui| {
+                ui.selectable_value(&mut sort, SortBy::Artist, "Artist");
+                ui.selectable_value(&mut sort, SortBy::Album, "Album");
+                ui.label("#");
+                ui.selectable_value(&mut sort, SortBy::Title, "Title");
+                ui.selectable_value(&mut sort, SortBy::Year, "Year");
+                ui.label("Problems");
+                ui.selectable_value(&mut sort, SortBy::Path, "File");
+                ui.end_row();
+
+                for track in self.sorted() {
+                    ui.label(&track.artist);
+                    ui.label(&track.album);
+                    ui.label(track.track_no.map(|n| n.to_string()).unwrap_or_default());
+                    ui.label(&track.title);
+                    ui.label(&track.year);
+                    problem_labels(ui, &track.problems);
+                    ui.horizontal(|ui| {
+                        if ui.small_button("Edit").clicked() {
+                            *open = Some(vec![track.path.to_path_buf()]);
+                        }
+                        ui.weak(track.path.file_name().unwrap_or_default().to_string_lossy());
+                    });
+                    ui.end_row();
+                }
+            });
+        });
+        self.sort = sort;
+    }
+
+    fn render_albums(&self, ui: &mut egui::Ui, open: &mut Option<Vec<PathBuf>>) {
+        // grouped by folder too, so same-named albums by different artists stay apart
+        let mut albums: BTreeMap<(String, PathBuf), Vec<&Track>> = BTreeMap::new();
+        for track in self.sorted() {
+            let folder = track.path.parent().unwrap_or(Path::new("")).to_path_buf();
+            albums.entry((track.album.to_string(), folder)).or_default().push(track);
+        }
+
+        egui::ScrollArea::vertical().id_source("library-albums").show(ui, |ui| {
+            egui::Grid::new("library-albums-grid").striped(true).show(ui, |ui| {
+                ui.strong("Album");
+                ui.strong("Artist");
+                ui.strong("Tracks");
+                ui.strong("Problems");
+                ui.end_row();
+
+                for ((album, _), tracks) in &albums {
+                    let mut problems: Vec<Problem> = tracks.iter().flat_map(|track| track.problems.iter().copied()).collect();
+                    problems.sort_by_key(|problem| problem.label());
+                    problems.dedup();
+
+                    ui.label(if album.is_empty() { "(no album)" } else { album });
+                    ui.label(&tracks[0].artist);
+                    ui.label(tracks.len().to_string());
+                    problem_labels(ui, &problems);
+                    if ui.small_button("Edit").clicked() {
+                        *open = Some(tracks.iter().map(|track| track.path.to_path_buf()).collect());
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    fn render_artists(&self, ui: &mut egui::Ui) {
+        let mut artists: BTreeMap<String, (usize, Vec<&str>)> = BTreeMap::new();
+        for track in self.sorted() {
+            let entry = artists.entry(track.artist.to_string()).or_default();
+            entry.0 += 1;
+            if !entry.1.contains(&track.album.as_str()) {
+                entry.1.push(&track.album);
+            }
+        }
+
+        egui::ScrollArea::vertical().id_source("library-artists").show(ui, |ui| {
+            egui::Grid::new("library-artists-grid").striped(true).show(ui, |ui| {
+                ui.strong("Artist");
+                ui.strong("Albums");
+                ui.strong("Tracks");
+                ui.end_row();
+
+                for (artist, (count, albums)) in &artists {
+                    ui.label(if artist.is_empty() { "(no artist)" } else { artist });
+                    ui.label(albums.len().to_string());
+                    ui.label(count.to_string());
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    fn render_duplicates(&mut self, ui: &mut egui::Ui, base_dir: &Path, layout: Layout) {
+        ui.horizontal(|ui| {
+            let compare = ui.add_enabled(!self.is_scanning(), egui::Checkbox::new(&mut self.fingerprints, "Compare Audio"))
+                .on_hover_text("Uses fpcalc (Chromaprint), needs to be in the $PATH");
+            if compare.changed() {
+                self.scan(ui.ctx(), base_dir, layout);
+            }
+            ui.separator();
+            ui.label("Replace copies with");
+            ui.radio_value(&mut self.resolution, Resolution::Hardlink, "Hardlinks");
+            ui.radio_value(&mut self.resolution, Resolution::Symlink, "Symlinks");
+            ui.radio_value(&mut self.resolution, Resolution::Reference, "Playlist References")
+                .on_hover_text("Deletes copies in playlist folders and points the .m3u8 at the kept file");
+        });
+        ui.separator();
+
+        let mut resolve = None;
+        egui::ScrollArea::vertical().id_source("library-duplicates").show(ui, |ui| {
+            for (n, group) in self.duplicates.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.strong(&self.tracks[group.keep].title);
+                    ui.weak(group.reason.label());
+                    if ui.add_enabled(self.scanning.is_none(), egui::Button::new("Resolve").small()).clicked() {
+                        resolve = Some(n);
+                    }
+                });
+                for i in &group.tracks {
+                    ui.radio_value(&mut group.keep, *i, self.tracks[*i].path.to_string_lossy())
+                        .on_hover_text("Copy to keep");
+                }
+                ui.separator();
+            }
+        });
+
+        if let Some(n) = resolve {
+            let group = &self.duplicates[n];
+            let keep = &self.tracks[group.keep].path;
+            let mut errors = Vec::new();
+            for i in group.tracks.iter().filter(|i| **i != group.keep) {
+                if let Err(e) = duplicates::resolve(keep, &self.tracks[*i].path, self.resolution) {
+                    errors.push(e);
+                }
+            }
+            self.notice = errors.join(", ");
+            self.scan(ui.ctx(), base_dir, layout);
+        }
+    }
+}
+
+/// Songs already in the library by video ID and by artist and title, for playlists that
+/// reference songs instead of storing their own copies. Copies in playlist folders are left out.
+pub struct Index {
+    by_video: HashMap<String, PathBuf>,
+    by_song: HashMap<String, PathBuf>
+}
+
+impl Index {
+    pub fn build(base_dir: &Path) -> Self {
+        let mut tracks = read_tracks(base_dir);
+        let playlists_dir = base_dir.join("playlists");
+        tracks.retain(|track| !track.path.starts_with(&playlists_dir));
+        tracks.sort_by_key(duplicates::location_rank);
+
+        let mut index = Index { by_video: HashMap::new(), by_song: HashMap::new() };
+        for track in tracks {
+            if !track.video_id.is_empty() {
+                index.by_video.entry(track.video_id.to_string()).or_insert(track.path.to_path_buf());
+            }
+            if let Some(key) = duplicates::song_key(&track.artist, &track.title) {
+                index.by_song.entry(key).or_insert(track.path);
+            }
+        }
+        return index
+    }
+
+    /// Adds a song stored after the index was built.
+    pub fn add(&mut self, video_id: &str, artist: &str, title: &str, path: &Path) {
+        if !video_id.is_empty() {
+            self.by_video.entry(video_id.to_string()).or_insert(path.to_path_buf());
+        }
+        if let Some(key) = duplicates::song_key(artist, title) {
+            self.by_song.entry(key).or_insert(path.to_path_buf());
+        }
+    }
+
+    pub fn find(&self, video_id: &str, artist: &str, title: &str) -> Option<&PathBuf> {
+        self.by_video.get(video_id)
+            .or_else(|| duplicates::song_key(artist, title).and_then(|key| self.by_song.get(&key)))
+    }
+}
+
+/// Every track with its problems and the duplicates among them, slow with `fingerprints`.
+fn scan(base_dir: &Path, layout: Layout, fingerprints: bool) -> (Vec<Track>, Vec<DuplicateGroup>) {
+    let mut tracks = read_tracks(base_dir);
+    for track in &mut tracks {
+        if track.track_no.is_none() && layout::in_album_folder(base_dir, layout, &track.path) {
+            track.problems.push(Problem::MissingTrackNumber);
+        }
+    }
+
+    let duplicates = duplicates::find(&tracks, fingerprints);
+    for group in &duplicates {
+        for i in &group.tracks {
+            tracks[*i].problems.push(Problem::Duplicate);
+        }
+    }
+    return (tracks, duplicates)
+}
+
+fn read_tracks(base_dir: &Path) -> Vec<Track> {
+    mp3_paths(base_dir).iter().map(|path| Track::read(path)).collect()
+}
+
+/// Every mp3 under `dir`.
+pub fn mp3_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    collect_mp3s(dir, &mut paths);
+    return paths
+}
+
+fn problem_labels(ui: &mut egui::Ui, problems: &[Problem]) {
+    ui.horizontal(|ui| {
+        for problem in problems {
+            ui.colored_label(ui.visuals().warn_fg_color, problem.label());
+        }
+    });
+}
+
+/// Every mp3 under `dir`, skipping download jobs still in progress.
+fn collect_mp3s(dir: &Path, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with(".bebops-") {
+                collect_mp3s(&path, paths);
+            }
+        } else if local::is_mp3(&path) {
+            paths.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn added_songs_are_found() {
+        let mut index = Index { by_video: HashMap::new(), by_song: HashMap::new() };
+        let path = Path::new("/music/singles/Song---Artist.mp3");
+        index.add("abc123", "Artist", "Song", path);
+
+        assert_eq!(index.find("abc123", "", ""), Some(&path.to_path_buf()));
+        // another upload of the same song
+        assert_eq!(index.find("xyz789", "Artist", "Song (Official Video)"), Some(&path.to_path_buf()));
+        assert_eq!(index.find("xyz789", "Artist", "Other Song"), None);
+    }
+}
@@ -0,0 +1,113 @@
+use eframe::egui;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{Single, Renderable, Downloadable, Appendable};
+use crate::single;
+use crate::rules::RuleEditor;
+use crate::metadata::Lookup;
+use crate::settings::Settings;
+
+/// mp3 files already on disk, edited with the same UI as downloads and re-tagged in place.
+#[derive(Default)]
+pub struct LocalFiles {
+    pub album_title: String,
+    pub album_artist: String,
+    pub songs: Vec<Single>,
+    /// Files that could not be read.
+    pub errors: Vec<String>,
+
+    pub rules: RuleEditor,
+    pub lookup: Lookup
+}
+
+impl LocalFiles {
+    pub fn open(paths: Vec<PathBuf>) -> Self {
+        let mut local = LocalFiles::default();
+        local.rules = RuleEditor::load();
+        for path in paths {
+            match Single::from_file(&path) {
+                Ok(song) => local.songs.push(song),
+                Err(e) => local.errors.push(e)
+            }
+        }
+
+        if let Some(song) = local.songs.first() {
+            local.album_title = song.album.to_string();
+            local.album_artist = song.artist.to_string();
+        }
+        local.lookup.title = local.album_title.to_string();
+        local.lookup.artist = local.album_artist.to_string();
+        return local
+    }
+
+    /// mp3 files directly inside `dir`, sorted by name.
+    pub fn open_folder(dir: &Path) -> Self {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| is_mp3(path))
+                .collect())
+            .unwrap_or_default();
+        paths.sort();
+        return LocalFiles::open(paths)
+    }
+}
+
+pub fn is_mp3(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"))
+}
+
+impl Appendable for LocalFiles {}
+
+impl Renderable for LocalFiles {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
+        ui.horizontal(|ui| {
+            ui.label("Album Title");
+            ui.text_edit_singleline(&mut self.album_title);
+            if ui.button("Apply").clicked() {
+                for song in self.songs.iter_mut().filter(|song| song.include) {
+                    song.album = self.album_title.clone();
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Album Artist");
+            ui.text_edit_singleline(&mut self.album_artist);
+            if ui.button("Apply").clicked() {
+                for song in self.songs.iter_mut().filter(|song| song.include) {
+                    song.artist = self.album_artist.clone();
+                }
+            }
+        });
+        if let Some(release) = self.lookup.render_release(ui, "local") {
+            self.album_title = release.title.to_string();
+            self.album_artist = release.artist.to_string();
+            single::apply_release(&mut self.songs, &release, false);
+        }
+        for error in &self.errors {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        self.rules.render(ui, "local", &mut self.songs);
+        single::render_selection(ui, &mut self.songs);
+        ui.separator();
+        for song in &mut self.songs {
+            if let Some(path) = &song.local_path {
+                ui.monospace(path.to_string_lossy());
+            }
+            ui.checkbox(&mut song.include, "Include");
+            song.render(ui, settings);
+            ui.separator();
+        }
+    }
+}
+
+impl Downloadable for LocalFiles {
+    /// Nothing is downloaded, the included files are re-tagged where they are.
+    fn download(&self, _base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        self.songs.iter()
+            .filter(|song| song.include)
+            .filter_map(|song| song.retag(&settings.cover).err().map(|e| song.problem(e)))
+            .collect()
+    }
+}
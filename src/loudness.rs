@@ -0,0 +1,262 @@
+use eframe::egui;
+use id3::{Tag, TagLike, Version};
+use id3::frame::ExtendedText;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use crate::utils;
+
+/// ReplayGain 2.0 reference level.
+const REFERENCE_LUFS: f64 = -18.0;
+
+const TRACK_GAIN: &str = "REPLAYGAIN_TRACK_GAIN";
+const TRACK_PEAK: &str = "REPLAYGAIN_TRACK_PEAK";
+const ALBUM_GAIN: &str = "REPLAYGAIN_ALBUM_GAIN";
+const ALBUM_PEAK: &str = "REPLAYGAIN_ALBUM_PEAK";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoudnessOptions {
+    pub replaygain: bool,
+    pub normalize: bool,
+    /// Integrated loudness `normalize` aims for.
+    pub target_lufs: f64,
+    pub true_peak: f64
+}
+
+impl Default for LoudnessOptions {
+    fn default() -> Self {
+        LoudnessOptions {
+            replaygain: false,
+            normalize: false,
+            target_lufs: -14.0,
+            true_peak: -1.0
+        }
+    }
+}
+
+impl LoudnessOptions {
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.replaygain, "Write ReplayGain Tags")
+            .on_hover_text("Track gain for every download, album gain for albums");
+        ui.checkbox(&mut self.normalize, "Normalize Audio")
+            .on_hover_text("Re-encodes each song with ffmpeg loudnorm");
+        ui.add_enabled_ui(self.normalize, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Target");
+                ui.add(egui::DragValue::new(&mut self.target_lufs)
+                    .clamp_range(-40.0..=-5.0)
+                    .speed(0.1)
+                    .suffix(" LUFS"));
+                ui.label("Peak");
+                ui.add(egui::DragValue::new(&mut self.true_peak)
+                    .clamp_range(-9.0..=0.0)
+                    .speed(0.1)
+                    .suffix(" dBTP"));
+            });
+        });
+    }
+}
+
+/// EBU R128 measurement, `peak` is the linear true peak.
+pub struct Loudness {
+    pub integrated: f64,
+    pub peak: f64
+}
+
+impl Loudness {
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LUFS - self.integrated
+    }
+}
+
+/// Measures `paths` as one continuous program, so a single file gives its track loudness
+/// and a whole album gives the album loudness.
+pub fn measure(paths: &[PathBuf]) -> Result<Loudness, String> {
+    if paths.is_empty() {
+        return Err("Nothing to measure".to_string())
+    }
+
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-nostats"]);
+    for path in paths {
+        command.arg("-i").arg(path);
+    }
+
+    let inputs: String = (0..paths.len()).map(|i| format!("[{}:a]", i)).collect();
+    let filter = format!("{}concat=n={}:v=0:a=1,ebur128=peak=true", inputs, paths.len());
+    let output = command
+        .args(["-filter_complex", &filter])
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    return parse_summary(&String::from_utf8_lossy(&output.stderr))
+}
+
+/// Reads the `Summary:` block ffmpeg's ebur128 filter prints at the end.
+/// Silent audio has no loudness (`-inf`), there's no gain to compute for it.
+fn parse_summary(stderr: &str) -> Result<Loudness, String> {
+    static INTEGRATED: OnceLock<Regex> = OnceLock::new();
+    static PEAK: OnceLock<Regex> = OnceLock::new();
+    let integrated = INTEGRATED.get_or_init(|| Regex::new(r"I:\s+(-?[\d.]+|-inf) LUFS").unwrap());
+    let peak = PEAK.get_or_init(|| Regex::new(r"True peak:\s+Peak:\s+(-?[\d.]+|-inf) dBFS").unwrap());
+
+    // the per-frame lines before it have `I:` values too
+    let (_, summary) = stderr.rsplit_once("Summary:").ok_or("No loudness summary")?;
+    let integrated = integrated.captures(summary)
+        .and_then(|caps| caps[1].parse::<f64>().ok())
+        .ok_or("No integrated loudness")?;
+    let peak_db = peak.captures(summary)
+        .and_then(|caps| caps[1].parse::<f64>().ok())
+        .ok_or("No true peak")?;
+    if !integrated.is_finite() || !peak_db.is_finite() {
+        return Err("Silent, no loudness to measure".to_string())
+    }
+
+    Ok(Loudness {
+        integrated,
+        peak: 10f64.powf(peak_db / 20.0)
+    })
+}
+
+/// Re-encodes `mp3_path` in place with ffmpeg loudnorm, before it is tagged.
+pub fn normalize(mp3_path: &Path, options: &LoudnessOptions) -> Result<(), String> {
+    if !options.normalize {
+        return Ok(())
+    }
+
+    let sample_rate = sample_rate(mp3_path)?;
+    let normalized = mp3_path.with_extension("norm.mp3");
+    let filter = format!("loudnorm=I={}:TP={}:LRA=11", options.target_lufs, options.true_peak);
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-y"])
+        .arg("-i").arg(mp3_path)
+        .args(["-af", &filter])
+        // loudnorm upsamples internally, put the source rate back
+        .args(["-ar", &sample_rate.to_string()])
+        .args(["-c:a", "libmp3lame", "-q:a", "0"])
+        .arg(&normalized)
+        .output()
+        .map_err(|e| format!("ffmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("normalize: {}", utils::error_line(&output.stderr, "failed")))
+    }
+    fs::rename(&normalized, mp3_path).map_err(|e| format!("normalize: {}", e))
+}
+
+/// Sample rate of the first audio stream, from ffprobe.
+fn sample_rate(path: &Path) -> Result<u32, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0"])
+        .args(["-show_entries", "stream=sample_rate", "-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("ffprobe: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("ffprobe: {}", utils::error_line(&output.stderr, "failed")))
+    }
+    parse_sample_rate(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse_sample_rate(stdout: &str) -> Result<u32, String> {
+    stdout.trim().parse().map_err(|_| "ffprobe: no sample rate".to_string())
+}
+
+/// Writes ReplayGain tags to the finished, already tagged `paths`.
+/// Album gain and peak are only written when `album` is set and cover all of `paths`.
+/// Returns what couldn't be measured or written.
+pub fn tag_replaygain(paths: &[PathBuf], album: bool, options: &LoudnessOptions) -> Vec<String> {
+    let mut errors = Vec::new();
+    if !options.replaygain || paths.is_empty() {
+        return errors
+    }
+
+    let album_loudness = if album {
+        match measure(paths) {
+            Ok(loudness) => Some(loudness),
+            Err(e) => {
+                errors.push(format!("album loudness: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    for path in paths {
+        let track = match measure(std::slice::from_ref(path)) {
+            Ok(loudness) => loudness,
+            Err(e) => {
+                errors.push(format!("{}: loudness: {}", path.display(), e));
+                continue;
+            }
+        };
+
+        let mut tag = match Tag::read_from_path(path) {
+            Ok(tag) => tag,
+            Err(_) => Tag::new()
+        };
+        set_text(&mut tag, TRACK_GAIN, format!("{:.2} dB", track.gain()));
+        set_text(&mut tag, TRACK_PEAK, format!("{:.6}", track.peak));
+        if let Some(album) = &album_loudness {
+            set_text(&mut tag, ALBUM_GAIN, format!("{:.2} dB", album.gain()));
+            set_text(&mut tag, ALBUM_PEAK, format!("{:.6}", album.peak));
+        }
+        if let Err(e) = tag.write_to_path(path, Version::Id3v24) {
+            errors.push(format!("{}: {}", path.display(), e));
+        }
+    }
+    return errors
+}
+
+fn set_text(tag: &mut Tag, description: &str, value: String) {
+    tag.remove_extended_text(Some(description), None);
+    tag.add_frame(ExtendedText {
+        description: description.to_string(),
+        value
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_rates_parse() {
+        assert_eq!(parse_sample_rate("48000\n"), Ok(48000));
+        assert_eq!(parse_sample_rate("44100"), Ok(44100));
+        assert!(parse_sample_rate("").is_err());
+        assert!(parse_sample_rate("N/A\n").is_err());
+    }
+
+    #[test]
+    fn summaries_parse() {
+        let stderr = "[Parsed_ebur128_0 @ 0x5581] t: 1.2 M: -20.1 S: -120.7 I: -19.8 LUFS\n\
+            [Parsed_ebur128_0 @ 0x5581] Summary:\n\n  Integrated loudness:\n    I:         -14.2 LUFS\n    Threshold: -24.4 LUFS\n\n  \
+            Loudness range:\n    LRA:         6.1 LU\n\n  True peak:\n    Peak:        0.0 dBFS\n";
+        let loudness = parse_summary(stderr).unwrap();
+        assert_eq!(loudness.integrated, -14.2);
+        assert_eq!(loudness.peak, 1.0);
+    }
+
+    #[test]
+    fn summaries_are_required() {
+        // per-frame lines only, the summary never came
+        let stderr = "[Parsed_ebur128_0 @ 0x5581] t: 1.2 M: -20.1 S: -120.7 I: -19.8 LUFS LRA: 0.0 LU\n";
+        assert_eq!(parse_summary(stderr).err().as_deref(), Some("No loudness summary"));
+        assert!(parse_summary("no summary here").is_err());
+    }
+
+    #[test]
+    fn silence_has_no_gain() {
+        let stderr = "[Parsed_ebur128_0 @ 0x5581] Summary:\n\n  Integrated loudness:\n    I:         -inf LUFS\n    Threshold: -70.0 LUFS\n\n  \
+            True peak:\n    Peak:        -inf dBFS\n";
+        assert!(parse_summary(stderr).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use id3::{Tag, TagLike};
+use id3::frame::{Lyrics as LyricsFrame, SynchronisedLyrics, SynchronisedLyricsType, TimestampFormat};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Lyrics for a song, synced lyrics are kept as LRC text so they can be edited directly.
+#[derive(Default)]
+pub struct Lyrics {
+    pub plain: String,
+    pub synced: String
+}
+
+pub trait LyricsProvider: Send + Sync {
+    fn name(&self) -> &str;
+    fn lyrics(&self, artist: &str, track: &str) -> Result<Lyrics, String>;
+}
+
+/// Provider for the LRCLIB API, `base_url` can point at a self hosted instance.
+pub struct Lrclib {
+    pub base_url: String
+}
+
+impl Default for Lrclib {
+    fn default() -> Self {
+        Lrclib {
+            base_url: "https://lrclib.net/api".to_string()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibResponse {
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>
+}
+
+impl LyricsProvider for Lrclib {
+    fn name(&self) -> &str {
+        "LRCLIB"
+    }
+
+    fn lyrics(&self, artist: &str, track: &str) -> Result<Lyrics, String> {
+        let user_agent = format!("bebops/{} ( https://github.com/JellyApple102/BeBops )", env!("CARGO_PKG_VERSION"));
+        let json = ureq::get(&format!("{}/get", self.base_url.trim_end_matches('/')))
+            .set("User-Agent", &user_agent)
+            .query("artist_name", artist)
+            .query("track_name", track)
+            .call()
+            .map_err(|e| e.to_string())?
+            .into_string()
+            .map_err(|e| e.to_string())?;
+
+        let response: LrclibResponse = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+        Ok(Lyrics {
+            plain: response.plain_lyrics.unwrap_or_default(),
+            synced: response.synced_lyrics.unwrap_or_default()
+        })
+    }
+}
+
+/// Parses LRC text into `(milliseconds, line)` pairs sorted by time.
+/// Lines with several timestamps (repeated choruses) are expanded, metadata tags like `[ar:...]` are skipped.
+pub fn parse_lrc(lrc: &str) -> Vec<(u32, String)> {
+    static TIMESTAMP: OnceLock<Regex> = OnceLock::new();
+    let timestamp = TIMESTAMP.get_or_init(|| Regex::new(r"\[(\d+):(\d{1,2})(?:[.:](\d{1,3}))?\]").unwrap());
+
+    let mut lines = Vec::new();
+    for line in lrc.lines() {
+        let mut times = Vec::new();
+        let mut text_start = 0;
+        for caps in timestamp.captures_iter(line) {
+            let whole = caps.get(0).unwrap();
+            // timestamps have to lead the line
+            if whole.start() != text_start {
+                break;
+            }
+            text_start = whole.end();
+
+            let mins: u32 = caps[1].parse().unwrap_or_default();
+            let secs: u32 = caps[2].parse().unwrap_or_default();
+            let fraction = caps.get(3).map(|m| m.as_str()).unwrap_or("0");
+            // ".5" is half a second, ".05" and ".050" are 50ms
+            let millis = format!("{:0<3}", fraction).parse::<u32>().unwrap_or_default();
+            times.push(mins * 60_000 + secs * 1000 + millis);
+        }
+
+        let text = line[text_start..].trim();
+        for time in times {
+            lines.push((time, text.to_string()));
+        }
+    }
+
+    lines.sort_by_key(|(time, _)| *time);
+    return lines
+}
+
+/// Synced lyrics with the timestamps dropped.
+pub fn plain_from_lrc(lrc: &str) -> String {
+    parse_lrc(lrc).into_iter()
+        .map(|(_, text)| text)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Synced lyrics read back from a SYLT frame, as LRC text.
+pub fn to_lrc(content: &[(u32, String)]) -> String {
+    content.iter()
+        .map(|(ms, text)| format!("[{:02}:{:02}.{:02}]{}", ms / 60_000, (ms / 1000) % 60, (ms % 1000) / 10, text))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Reads an `.lrc` file, also used for subtitles yt-dlp converted to LRC.
+pub fn read_lrc(path: &Path) -> Result<Lyrics, String> {
+    let synced = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Ok(Lyrics {
+        plain: plain_from_lrc(&synced),
+        synced
+    })
+}
+
+/// Subtitles yt-dlp wrote for `stem` and converted to LRC, named `<stem>.<lang>.lrc`.
+pub fn find_subtitles(dir: &Path, stem: &str) -> Option<PathBuf> {
+    let prefix = format!("{}.", stem);
+    fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(".lrc")
+        })
+}
+
+/// Adds USLT (plain) and SYLT (synced) frames, plain lyrics fall back to the synced text.
+pub fn add_to_tag(tag: &mut Tag, plain: &str, synced: &str) {
+    let plain = if plain.trim().is_empty() { plain_from_lrc(synced) } else { plain.to_string() };
+    if !plain.trim().is_empty() {
+        tag.add_frame(LyricsFrame {
+            lang: "eng".to_string(),
+            description: String::default(),
+            text: plain
+        });
+    }
+
+    let content = parse_lrc(synced);
+    if !content.is_empty() {
+        tag.add_frame(SynchronisedLyrics {
+            lang: "eng".to_string(),
+            timestamp_format: TimestampFormat::Ms,
+            content_type: SynchronisedLyricsType::Lyrics,
+            description: String::default(),
+            content
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_lines_are_expanded() {
+        let lines = parse_lrc("[00:10.00][01:10.00]Chorus\n[00:05.00]Verse");
+        assert_eq!(lines, [(5000, "Verse".to_string()), (10_000, "Chorus".to_string()), (70_000, "Chorus".to_string())]);
+    }
+
+    #[test]
+    fn fractions_are_parts_of_a_second() {
+        let lines = parse_lrc("[00:01.5]a\n[00:02.05]b\n[00:03.050]c\n[00:04.25]d\n[00:05.250]e\n[00:06]f");
+        let times: Vec<u32> = lines.iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, [1500, 2050, 3050, 4250, 5250, 6000]);
+    }
+
+    #[test]
+    fn metadata_lines_are_skipped() {
+        let lrc = "[ar:Artist]\n[ti:Title]\n[length:03:20]\n[00:01.00]Line\nText [00:02.00] in the middle";
+        assert_eq!(parse_lrc(lrc), [(1000, "Line".to_string())]);
+        assert_eq!(plain_from_lrc(lrc), "Line");
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let lrc = "[00:01.50]First\n[01:02.25]Second";
+        let mut tag = Tag::new();
+        add_to_tag(&mut tag, "", lrc);
+
+        let mut bytes = Vec::new();
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+        let tag = Tag::read_from2(std::io::Cursor::new(bytes)).unwrap();
+
+        assert_eq!(tag.lyrics().next().unwrap().text, "First\nSecond");
+        let synced = tag.synchronised_lyrics().next().unwrap();
+        assert_eq!(synced.content, [(1500, "First".to_string()), (62_250, "Second".to_string())]);
+        assert_eq!(to_lrc(&synced.content), lrc);
+    }
+}
@@ -12,18 +12,52 @@ mod playlist;
 mod album;
 mod fullvideoplaylist;
 mod fullvideoalbum;
+mod cover;
+mod details;
+mod duplicates;
+mod extractor;
+mod layout;
+mod library;
+mod local;
+mod loudness;
+mod lyrics;
+mod metadata;
+mod preview;
+mod rules;
+mod search;
+mod settings;
+mod sponsorblock;
+mod title;
+mod trim;
 mod utils;
 use single::Single;
 use playlist::Playlist;
 use album::Album;
 use fullvideoplaylist::FullVideoPlaylist;
 use fullvideoalbum::FullVideoAlbum;
+use library::Library;
+use local::LocalFiles;
+use search::{Search, Pick, Thumbnail};
+use settings::Settings;
 
 struct MyApp {
     base_download_dir: PathBuf,
     current_url_string: String,
     current_download_type: DownloadType,
-    content: Option<Box<dyn RendDownable>>
+    content: Option<Box<dyn RendDownable>>,
+
+    settings: Settings,
+    show_settings: bool,
+
+    library: Library,
+    show_library: bool,
+
+    search: Search,
+    show_search: bool,
+
+    /// Outcome of the last fetch or download, and what went wrong in it.
+    status: String,
+    problems: Vec<String>
 }
 
 impl Default for MyApp {
@@ -32,23 +66,45 @@ impl Default for MyApp {
             base_download_dir: PathBuf::default(),
             current_url_string: String::default(),
             current_download_type: DownloadType::default(),
-            content: None
+            content: None,
+            settings: Settings::default(),
+            show_settings: false,
+            library: Library::default(),
+            show_library: false,
+            search: Search::default(),
+            show_search: false,
+            status: String::default(),
+            problems: Vec::new()
         }
     }
 }
 
 trait Renderable {
-    fn render(&mut self, ui: &mut egui::Ui);
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings);
 }
 
 trait Downloadable {
-    fn download(&self, base_dir: &PathBuf);
+    /// Returns what went wrong, downloads carry on past anything that only affects one step or song.
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String>;
+
+    /// Whether everything needed for the download is there, songs of flat fetches need their details first.
+    fn ready(&self) -> bool {
+        true
+    }
+}
+
+trait Appendable {
+    /// Adds fetched videos to the content, `split_chapters` makes each chapter its own song.
+    /// Returns false for content that can't be added to.
+    fn append(&mut self, _urls: Vec<UrlInfo>, _split_chapters: bool) -> bool {
+        return false
+    }
 }
 
-trait RendDownable: Renderable + Downloadable {}
-impl<T> RendDownable for T where T: Renderable + Downloadable {}
+trait RendDownable: Renderable + Downloadable + Appendable {}
+impl<T> RendDownable for T where T: Renderable + Downloadable + Appendable {}
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Clone, Copy)]
 enum DownloadType {
     #[default]
     Single,
@@ -58,27 +114,86 @@ enum DownloadType {
     FullVideoAlbum
 }
 
+impl DownloadType {
+    fn render(&mut self, ui: &mut egui::Ui, id_source: &str) {
+        egui::ComboBox::from_id_source(id_source)
+            .selected_text(format!("{:?}", self))
+            .width(120.0)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(self, DownloadType::Single, "Single");
+                ui.selectable_value(self, DownloadType::Playlist, "Playlist");
+                ui.selectable_value(self, DownloadType::Album, "Album");
+                ui.selectable_value(self, DownloadType::FullVideoPlaylist, "Full Video Playlist");
+                ui.selectable_value(self, DownloadType::FullVideoAlbum, "Full Video Album");
+            });
+    }
+}
+
 #[derive(Deserialize, Default)]
 #[allow(dead_code)]
 struct UrlInfo {
+    #[serde(default, deserialize_with = "utils::null_default")]
+    id: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
     webpage_url: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
     title: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
     uploader: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    channel: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
     thumbnail: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
     description: String,
+    duration: Option<f32>,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    extractor_key: String,
+
+    // what `--flat-playlist` gives instead of `webpage_url` and `thumbnail`
+    #[serde(default, rename = "_type", deserialize_with = "utils::null_default")]
+    entry_type: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    url: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    thumbnails: Vec<Thumbnail>,
 
-    #[serde(default)]
+    #[serde(default, deserialize_with = "utils::null_default")]
     track: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "utils::null_default")]
     artist: String,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "utils::null_default")]
     album: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    album_artist: String,
+    track_number: Option<usize>,
+    release_year: Option<u32>,
+    release_date: Option<String>,
+    upload_date: Option<String>,
 
     playlist: Option<String>,
     // playlist_index: Option<u32>,
     chapters: Option<Vec<Chapter>>
 }
 
+impl UrlInfo {
+    /// Only listed by `--flat-playlist`, the rest of the info is fetched later.
+    fn is_flat(&self) -> bool {
+        self.entry_type == "url"
+    }
+
+    fn fill_fallbacks(&mut self) {
+        if self.webpage_url.is_empty() {
+            self.webpage_url = self.url.to_string();
+        }
+        if self.thumbnail.is_empty() {
+            if let Some(thumbnail) = search::best_thumbnail(&self.thumbnails) {
+                self.thumbnail = thumbnail.url.to_string();
+            }
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct Chapter {
     pub start_time: f32,
@@ -89,6 +204,7 @@ pub struct Chapter {
 impl MyApp {
     fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         let mut app = Self::default();
+        app.settings = Settings::load();
 
         let dir = dirs::audio_dir().unwrap().join("bebops");
         app.update_download_dir(dir);
@@ -102,20 +218,17 @@ impl MyApp {
     }
 
     fn fetch(&mut self) {
-        let command = Command::new("yt-dlp")
-            .arg("-j")
-            .arg(&self.current_url_string)
-            .output()
-            .expect("failed to make fetch command");
-
-        let binding = String::from_utf8(command.stdout).unwrap();
-        let output = binding.trim();
-        let jsons: Vec<&str> = output.split('\n').collect();
-        let mut urls: Vec<UrlInfo> = Vec::with_capacity(jsons.len());
-        for json in jsons {
-            let info: UrlInfo = serde_json::from_str(json).unwrap();
-            urls.push(info)
-        }
+        // playlists are listed right away, the details of each song come in after
+        let flat = matches!(self.current_download_type, DownloadType::Playlist | DownloadType::Album);
+        let mut urls = match fetch_info(&self.current_url_string, flat) {
+            Ok(urls) => urls,
+            Err(e) => {
+                self.status = format!("Fetch failed: {}", e);
+                return
+            }
+        };
+        self.status = String::default();
+        self.problems = Vec::new();
 
         self.content = match self.current_download_type {
             DownloadType::Single => Some(Box::new(Single::from(urls.swap_remove(0)))),
@@ -126,9 +239,35 @@ impl MyApp {
         }
     }
 
+    /// Adds the url to the current content instead of replacing it.
+    /// Full video types split the fetched videos by chapter.
+    fn append(&mut self) {
+        let content = match &mut self.content {
+            Some(content) => content,
+            None => {
+                self.fetch();
+                return
+            }
+        };
+        let split_chapters = matches!(self.current_download_type, DownloadType::FullVideoPlaylist | DownloadType::FullVideoAlbum);
+        match fetch_info(&self.current_url_string, !split_chapters) {
+            Ok(urls) => {
+                if content.append(urls, split_chapters) {
+                    self.current_url_string = String::default();
+                    self.status = String::default();
+                } else {
+                    self.status = "Only albums and playlists can be appended to".to_string();
+                }
+            },
+            Err(e) => self.status = format!("Fetch failed: {}", e)
+        }
+    }
+
     fn clear_content(&mut self) {
         self.current_url_string = String::default();
         self.content = None;
+        self.status = String::default();
+        self.problems = Vec::new();
     }
 
     fn render_header(&mut self, ui: &mut egui::Ui) {
@@ -137,34 +276,60 @@ impl MyApp {
                 let url_text_edit = egui::TextEdit::singleline(&mut self.current_url_string);
                 ui.add(url_text_edit);
 
-                egui::ComboBox::from_id_source("Download Type")
-                    .selected_text(format!("{:?}", self.current_download_type))
-                    .width(120.0)
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut self.current_download_type, DownloadType::Single, "Single");
-                        ui.selectable_value(&mut self.current_download_type, DownloadType::Playlist, "Playlist");
-                        ui.selectable_value(&mut self.current_download_type, DownloadType::Album, "Album");
-                        ui.selectable_value(&mut self.current_download_type, DownloadType::FullVideoPlaylist, "Full Video Playlist");
-                        ui.selectable_value(&mut self.current_download_type, DownloadType::FullVideoAlbum, "Full Video Album");
-                    });
+                self.current_download_type.render(ui, "Download Type");
 
                 if ui.button("Fetch").clicked() {
                     self.fetch();
                 }
 
-                if ui.button("Download").clicked() {
+                if ui.button("Append").on_hover_text("Add to the current album or playlist").clicked() {
+                    self.append();
+                }
+
+                if ui.button("Open Files").on_hover_text("Edit the tags of mp3 files already on disk").clicked() {
+                    let fd = rfd::FileDialog::new()
+                        .add_filter("mp3", &["mp3"]);
+
+                    if let Some(paths) = fd.pick_files() {
+                        self.content = Some(Box::new(LocalFiles::open(paths)));
+                    }
+                }
+
+                if ui.button("Open Folder").clicked() {
+                    if let Some(dir) = rfd::FileDialog::new().pick_folder() {
+                        self.content = Some(Box::new(LocalFiles::open_folder(&dir)));
+                    }
+                }
+
+                let ready = self.content.as_ref().is_some_and(|content| content.ready());
+                let download = ui.add_enabled(ready, egui::Button::new("Download"))
+                    .on_disabled_hover_text("Waiting for song details");
+                if download.clicked() {
                     if let Some(content) = &mut self.content {
-                        content.download(&self.base_download_dir);
+                        self.problems = content.download(&self.base_download_dir, &self.settings);
+                        self.status = if self.problems.is_empty() {
+                            "Downloaded".to_string()
+                        } else {
+                            format!("Downloaded with {} problems", self.problems.len())
+                        };
                     }
                 }
 
                 if ui.button("Clear").clicked() {
                     self.clear_content();
                 }
+
+                ui.label(&self.status);
             });
 
             ui.with_layout(egui::Layout::right_to_left(egui::Align::TOP), |ui| {
                 ui.hyperlink_to("GitHub", "https://github.com/JellyApple102/BeBops");
+                ui.toggle_value(&mut self.show_settings, "Settings");
+                ui.toggle_value(&mut self.show_search, "Search");
+                let first_open = self.library.tracks.is_empty() && !self.library.is_scanning();
+                if ui.toggle_value(&mut self.show_library, "Library").clicked() && self.show_library && first_open {
+                    self.library.scan(ui.ctx(), &self.base_download_dir, self.settings.layout);
+                }
             });
         });
     }
@@ -172,18 +337,92 @@ impl MyApp {
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::Window::new("Settings")
+            .open(&mut self.show_settings)
+            .show(ctx, |ui| {
+                self.settings.render(ui);
+            });
+
+        let mut open = None;
+        egui::Window::new("Library")
+            .open(&mut self.show_library)
+            .default_size([720.0, 480.0])
+            .show(ctx, |ui| {
+                open = self.library.render(ui, &self.base_download_dir, self.settings.layout);
+            });
+        if let Some(paths) = open {
+            self.content = Some(Box::new(LocalFiles::open(paths)));
+        }
+
+        let mut pick = None;
+        egui::Window::new("Search")
+            .open(&mut self.show_search)
+            .default_size([480.0, 480.0])
+            .show(ctx, |ui| {
+                pick = self.search.render(ui);
+            });
+        match pick {
+            Some(Pick::Fetch(url, download_type)) => {
+                self.current_url_string = url;
+                self.current_download_type = download_type;
+                self.fetch();
+            },
+            Some(Pick::Append(url, download_type)) => {
+                self.current_url_string = url;
+                self.current_download_type = download_type;
+                self.append();
+            },
+            None => {}
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             self.render_header(ui);
+            if !self.problems.is_empty() {
+                ui.collapsing("Problems", |ui| {
+                    for problem in &self.problems {
+                        ui.colored_label(ui.visuals().error_fg_color, problem);
+                    }
+                });
+            }
             ui.separator();
             egui::containers::ScrollArea::vertical().show(ui, |ui| {
                 if let Some(content) = &mut self.content {
-                    content.render(ui);
+                    content.render(ui, &self.settings);
                 }
             });
         });
     }
 }
 
+/// Video info from `yt-dlp -j`, one entry per video of a playlist.
+/// With `flat` playlist entries are only listed, see `UrlInfo::is_flat`.
+/// Entries yt-dlp couldn't resolve are left out, it's an error when none are left.
+fn fetch_info(url: &str, flat: bool) -> Result<Vec<UrlInfo>, String> {
+    let mut command = Command::new("yt-dlp");
+    command.arg("-j");
+    if flat {
+        command.arg("--flat-playlist");
+    }
+    let output = command
+        .arg(url.trim())
+        .output()
+        .map_err(|e| format!("yt-dlp: {}", e))?;
+
+    let binding = String::from_utf8_lossy(&output.stdout);
+    let mut urls: Vec<UrlInfo> = Vec::new();
+    for json in binding.trim().lines() {
+        if let Ok(mut info) = serde_json::from_str::<UrlInfo>(json) {
+            info.fill_fallbacks();
+            urls.push(info)
+        }
+    }
+
+    if urls.is_empty() {
+        return Err(utils::error_line(&output.stderr, "nothing found"))
+    }
+    return Ok(urls)
+}
+
 fn main() -> Result<(), eframe::Error> {
     const VERSION: &str = env!("CARGO_PKG_VERSION");
 
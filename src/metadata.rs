@@ -0,0 +1,578 @@
+use eframe::egui;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::utils::Task;
+
+// MusicBrainz allows one request per second per client
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A candidate release returned by a `MetadataProvider`.
+/// Search results may come back without `tracks`, use `MetadataProvider::release` to fill them.
+#[derive(Clone, Default)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub year: String,
+    pub track_count: usize,
+    pub tracks: Vec<ReleaseTrack>
+}
+
+#[derive(Clone, Default)]
+pub struct ReleaseTrack {
+    pub recording_id: String,
+    pub title: String,
+    pub artist: String,
+    /// Disc the track is on, from 1.
+    pub disc: usize,
+    /// Track number on its disc, from 1.
+    pub position: usize
+}
+
+pub trait MetadataProvider: Send + Sync {
+    fn name(&self) -> &str;
+    /// Releases matching an album title and artist.
+    fn search_releases(&self, album: &str, artist: &str) -> Result<Vec<Release>, String>;
+    /// Full release with its tracklist.
+    fn release(&self, id: &str) -> Result<Release, String>;
+    /// Releases containing a recording, each with that single recording as its tracklist.
+    fn search_recordings(&self, track: &str, artist: &str) -> Result<Vec<Release>, String>;
+}
+
+/// Provider for the MusicBrainz web service (`/ws/2`) JSON API.
+/// `base_url` can point at a mirror or a local server serving the same responses.
+pub struct MusicBrainz {
+    pub base_url: String
+}
+
+impl Default for MusicBrainz {
+    fn default() -> Self {
+        MusicBrainz {
+            base_url: "https://musicbrainz.org/ws/2".to_string()
+        }
+    }
+}
+
+impl MusicBrainz {
+    fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<String, String> {
+        // shared by every lookup, they all count against the same limit
+        static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+        let mut last_request = LAST_REQUEST.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(wait) = last_request.and_then(|last| REQUEST_INTERVAL.checked_sub(last.elapsed())) {
+            thread::sleep(wait);
+        }
+        *last_request = Some(Instant::now());
+
+        let user_agent = format!("bebops/{} ( https://github.com/JellyApple102/BeBops )", env!("CARGO_PKG_VERSION"));
+        let mut request = ureq::get(&format!("{}/{}", self.base_url.trim_end_matches('/'), path))
+            .set("User-Agent", &user_agent)
+            .query("fmt", "json");
+        for (key, value) in query {
+            request = request.query(key, value);
+        }
+
+        request.call()
+            .map_err(|e| e.to_string())?
+            .into_string()
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl MetadataProvider for MusicBrainz {
+    fn name(&self) -> &str {
+        "MusicBrainz"
+    }
+
+    fn search_releases(&self, album: &str, artist: &str) -> Result<Vec<Release>, String> {
+        let query = lucene_query(&[("release", album), ("artist", artist)]);
+        let json = self.get("release", &[("query", &query), ("limit", "10")])?;
+        parse_release_search(&json)
+    }
+
+    fn release(&self, id: &str) -> Result<Release, String> {
+        let json = self.get(&format!("release/{}", id), &[("inc", "recordings+artist-credits")])?;
+        parse_release(&json)
+    }
+
+    fn search_recordings(&self, track: &str, artist: &str) -> Result<Vec<Release>, String> {
+        let query = lucene_query(&[("recording", track), ("artist", artist)]);
+        let json = self.get("recording", &[("query", &query), ("limit", "10")])?;
+        parse_recording_search(&json)
+    }
+}
+
+fn lucene_query(fields: &[(&str, &str)]) -> String {
+    fields.iter()
+        .filter(|(_, value)| !value.trim().is_empty())
+        .map(|(field, value)| format!("{}:\"{}\"", field, value.replace(['"', '\\'], "")))
+        .collect::<Vec<String>>()
+        .join(" AND ")
+}
+
+#[derive(Deserialize)]
+struct MbArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String
+}
+
+fn credit_name(credits: &[MbArtistCredit]) -> String {
+    credits.iter()
+        .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+        .collect()
+}
+
+fn year(date: &Option<String>) -> String {
+    date.as_deref().unwrap_or_default().chars().take(4).collect()
+}
+
+#[derive(Deserialize)]
+struct MbRelease {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    #[serde(default)]
+    media: Vec<MbMedium>
+}
+
+#[derive(Deserialize)]
+struct MbMedium {
+    position: Option<usize>,
+    #[serde(rename = "track-count", default)]
+    track_count: usize,
+    #[serde(default)]
+    tracks: Vec<MbTrack>
+}
+
+#[derive(Deserialize)]
+struct MbTrack {
+    position: Option<usize>,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    recording: MbRecordingRef
+}
+
+#[derive(Deserialize)]
+struct MbRecordingRef {
+    id: String
+}
+
+#[derive(Deserialize)]
+struct MbReleaseSearch {
+    releases: Vec<MbRelease>
+}
+
+#[derive(Deserialize)]
+struct MbRecording {
+    id: String,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<MbArtistCredit>,
+    #[serde(default)]
+    releases: Vec<MbRelease>
+}
+
+#[derive(Deserialize)]
+struct MbRecordingSearch {
+    recordings: Vec<MbRecording>
+}
+
+impl From<MbRelease> for Release {
+    fn from(mb: MbRelease) -> Self {
+        let artist = credit_name(&mb.artist_credit);
+        let mut tracks = Vec::new();
+        for (i, medium) in mb.media.iter().enumerate() {
+            for (j, track) in medium.tracks.iter().enumerate() {
+                let track_artist = credit_name(&track.artist_credit);
+                tracks.push(ReleaseTrack {
+                    recording_id: track.recording.id.to_string(),
+                    title: track.title.to_string(),
+                    artist: if track_artist.is_empty() { artist.to_string() } else { track_artist },
+                    disc: medium.position.unwrap_or(i + 1),
+                    position: track.position.unwrap_or(j + 1)
+                });
+            }
+        }
+
+        Release {
+            year: year(&mb.date),
+            track_count: mb.media.iter().map(|medium| medium.track_count.max(medium.tracks.len())).sum(),
+            id: mb.id,
+            title: mb.title,
+            artist,
+            tracks
+        }
+    }
+}
+
+pub fn parse_release_search(json: &str) -> Result<Vec<Release>, String> {
+    let search: MbReleaseSearch = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    Ok(search.releases.into_iter().map(Release::from).collect())
+}
+
+pub fn parse_release(json: &str) -> Result<Release, String> {
+    let release: MbRelease = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    Ok(Release::from(release))
+}
+
+pub fn parse_recording_search(json: &str) -> Result<Vec<Release>, String> {
+    let search: MbRecordingSearch = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let mut releases = Vec::new();
+    for recording in search.recordings {
+        let artist = credit_name(&recording.artist_credit);
+        for mb in recording.releases {
+            releases.push(Release {
+                id: mb.id,
+                title: mb.title,
+                artist: artist.to_string(),
+                year: year(&mb.date),
+                track_count: 1,
+                tracks: vec![ReleaseTrack {
+                    recording_id: recording.id.to_string(),
+                    title: recording.title.to_string(),
+                    artist: artist.to_string(),
+                    disc: 1,
+                    position: 1
+                }]
+            });
+        }
+    }
+    Ok(releases)
+}
+
+/// Search state and candidate picker shown in the album and song views.
+pub struct Lookup {
+    pub provider: Arc<dyn MetadataProvider>,
+    pub title: String,
+    pub artist: String,
+    pub results: Vec<Release>,
+    pub selected: Option<usize>,
+    pub status: String,
+
+    searching: Option<Task<Vec<Release>>>,
+    /// Tracklist being fetched for the result at that index.
+    loading: Option<(usize, Task<Release>)>
+}
+
+impl Default for Lookup {
+    fn default() -> Self {
+        Lookup {
+            provider: Arc::new(MusicBrainz::default()),
+            title: String::default(),
+            artist: String::default(),
+            results: Vec::new(),
+            selected: None,
+            status: String::default(),
+            searching: None,
+            loading: None
+        }
+    }
+}
+
+impl Lookup {
+    /// Renders the release search, returns the release to apply once the user picks one.
+    pub fn render_release(&mut self, ui: &mut egui::Ui, id: &str) -> Option<Release> {
+        self.render(ui, id, "Album", true)
+    }
+
+    /// Renders the recording search, returns the release to apply once the user picks one.
+    pub fn render_recording(&mut self, ui: &mut egui::Ui, id: &str) -> Option<Release> {
+        self.render(ui, id, "Title", false)
+    }
+
+    fn render(&mut self, ui: &mut egui::Ui, id: &str, title_label: &str, releases: bool) -> Option<Release> {
+        let mut picked = None;
+        self.poll();
+
+        egui::CollapsingHeader::new(format!("Lookup ({})", self.provider.name()))
+            .id_source(format!("{id}-lookup"))
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(title_label);
+                    ui.text_edit_singleline(&mut self.title);
+                    ui.label("Artist");
+                    ui.text_edit_singleline(&mut self.artist);
+
+                    if ui.button("Search").clicked() {
+                        let provider = Arc::clone(&self.provider);
+                        let (title, artist) = (self.title.to_string(), self.artist.to_string());
+                        self.searching = Some(Task::spawn(ui.ctx(), move || {
+                            if releases {
+                                provider.search_releases(&title, &artist)
+                            } else {
+                                provider.search_recordings(&title, &artist)
+                            }
+                        }));
+                        self.loading = None;
+                        self.selected = None;
+                    }
+                    if self.searching.is_some() || self.loading.is_some() {
+                        ui.spinner();
+                    }
+                    ui.label(&self.status);
+                });
+
+                let mut clicked = None;
+                for (i, release) in self.results.iter().enumerate() {
+                    let mut text = format!("{} — {}", release.title, release.artist);
+                    if !release.year.is_empty() {
+                        text.push_str(&format!(" ({})", release.year));
+                    }
+                    if releases {
+                        text.push_str(&format!(" · {} tracks", release.track_count));
+                    } else if let Some(track) = release.tracks.first() {
+                        text = format!("{} · {}", track.title, text);
+                    }
+                    if ui.selectable_label(self.selected == Some(i), text).clicked() {
+                        clicked = Some(i);
+                    }
+                }
+
+                if let Some(i) = clicked {
+                    self.selected = Some(i);
+                    if releases && self.results[i].tracks.is_empty() {
+                        let provider = Arc::clone(&self.provider);
+                        let release_id = self.results[i].id.to_string();
+                        self.loading = Some((i, Task::spawn(ui.ctx(), move || provider.release(&release_id))));
+                    }
+                }
+
+                if let Some(release) = self.selected.and_then(|i| self.results.get(i)) {
+                    if releases {
+                        ui.separator();
+                        let multi_disc = release.tracks.iter().any(|track| track.disc > 1);
+                        for track in &release.tracks {
+                            let number = if multi_disc { format!("{}-{}", track.disc, track.position) } else { track.position.to_string() };
+                            ui.label(format!("{}. {} — {}", number, track.title, track.artist));
+                        }
+                    }
+                    // a release without its tracklist yet can't be applied
+                    if ui.add_enabled(self.loading.is_none(), egui::Button::new("Apply")).clicked() {
+                        picked = Some(release.clone());
+                    }
+                }
+            });
+
+        return picked
+    }
+
+    fn poll(&mut self) {
+        if let Some(results) = self.searching.as_ref().and_then(|task| task.poll()) {
+            self.searching = None;
+            match results {
+                Ok(results) => {
+                    self.status = format!("{} results", results.len());
+                    self.results = results;
+                },
+                Err(e) => self.status = e
+            }
+        }
+
+        if let Some((i, release)) = self.loading.as_ref().and_then(|(i, task)| Some((*i, task.poll()?))) {
+            self.loading = None;
+            match release {
+                Ok(release) => self.results[i] = release,
+                Err(e) => self.status = e
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // trimmed down `/ws/2/release?query=...&fmt=json` response
+    const RELEASE_SEARCH: &str = r#"{
+        "created": "2023-10-01T12:00:00.000Z",
+        "count": 2,
+        "offset": 0,
+        "releases": [
+            {
+                "id": "f5093c06-23e3-404f-aeaa-40f72885ee3a",
+                "score": 100,
+                "title": "Abbey Road",
+                "status": "Official",
+                "date": "1969-09-26",
+                "country": "GB",
+                "artist-credit": [{"name": "The Beatles", "artist": {"id": "b10bbbfc-cf9e-42e0-be17-e2c3e1d2600d", "name": "The Beatles"}}],
+                "track-count": 17,
+                "media": [{"format": "12\" Vinyl", "disc-count": 0, "track-count": 17}]
+            },
+            {
+                "id": "2f6b1bb9-6f1d-4a45-a8f4-cf2ec9a9b6c4",
+                "score": 92,
+                "title": "Duets",
+                "artist-credit": [
+                    {"name": "Artist One", "joinphrase": " & "},
+                    {"name": "Artist Two"}
+                ],
+                "media": [{"track-count": 10}, {"track-count": 8}]
+            }
+        ]
+    }"#;
+
+    // trimmed down `/ws/2/recording?query=...&fmt=json` response
+    const RECORDING_SEARCH: &str = r#"{
+        "count": 1,
+        "offset": 0,
+        "recordings": [
+            {
+                "id": "b3a3c3b6-0e6f-4d8e-9d9f-6a1f3f0b2c11",
+                "score": 100,
+                "title": "Come Together",
+                "length": 259946,
+                "artist-credit": [{"name": "The Beatles", "joinphrase": ""}],
+                "releases": [
+                    {"id": "f5093c06-23e3-404f-aeaa-40f72885ee3a", "title": "Abbey Road", "date": "1969"},
+                    {"id": "0a1b2c3d-0000-4000-8000-000000000001", "title": "1", "date": null}
+                ]
+            },
+            {
+                "id": "c0ffee00-0000-4000-8000-000000000002",
+                "title": "Come Together (Demo)",
+                "artist-credit": [{"name": "The Beatles"}]
+            }
+        ]
+    }"#;
+
+    // trimmed down `/ws/2/release/<id>?inc=recordings+artist-credits&fmt=json` response
+    const RELEASE: &str = r#"{
+        "id": "7d7a9a5f-2c7e-4a3e-9a57-8a1c2b3d4e5f",
+        "title": "Double Album",
+        "date": "2001-05-14",
+        "status": "Official",
+        "artist-credit": [{"name": "Band", "joinphrase": "", "artist": {"id": "a0", "name": "Band"}}],
+        "media": [
+            {
+                "position": 1,
+                "format": "CD",
+                "track-count": 2,
+                "tracks": [
+                    {"id": "t1", "position": 1, "number": "1", "title": "Opener", "length": 200000,
+                     "recording": {"id": "rec-1", "title": "Opener"}},
+                    {"id": "t2", "position": 2, "number": "2", "title": "Duet",
+                     "artist-credit": [{"name": "Band", "joinphrase": " feat. "}, {"name": "Guest"}],
+                     "recording": {"id": "rec-2", "title": "Duet"}}
+                ]
+            },
+            {
+                "position": 2,
+                "format": "CD",
+                "track-count": 1,
+                "tracks": [
+                    {"id": "t3", "position": 1, "number": "1", "title": "Closer",
+                     "recording": {"id": "rec-3", "title": "Closer"}}
+                ]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn release_maps_tracks_per_disc() {
+        let release = parse_release(RELEASE).unwrap();
+        assert_eq!(release.title, "Double Album");
+        assert_eq!(release.artist, "Band");
+        assert_eq!(release.year, "2001");
+        assert_eq!(release.track_count, 3);
+
+        let tracks: Vec<(usize, usize, &str, &str, &str)> = release.tracks.iter()
+            .map(|track| (track.disc, track.position, track.title.as_str(), track.artist.as_str(), track.recording_id.as_str()))
+            .collect();
+        assert_eq!(tracks, [
+            (1, 1, "Opener", "Band", "rec-1"),
+            (1, 2, "Duet", "Band feat. Guest", "rec-2"),
+            (2, 1, "Closer", "Band", "rec-3")
+        ]);
+    }
+
+    /// Stand-in server answering `count` requests with `body`, returns its url and the request lines it got.
+    fn serve(count: usize, body: &'static str) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for _ in 0..count {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string());
+            }
+            return requests
+        });
+        return (url, handle)
+    }
+
+    #[test]
+    fn releases_are_fetched_one_request_per_second() {
+        let (url, server) = serve(2, RELEASE);
+        let provider = MusicBrainz { base_url: url };
+
+        let started = Instant::now();
+        let release = provider.release("7d7a9a5f-2c7e-4a3e-9a57-8a1c2b3d4e5f").unwrap();
+        provider.release("7d7a9a5f-2c7e-4a3e-9a57-8a1c2b3d4e5f").unwrap();
+        assert!(started.elapsed() >= REQUEST_INTERVAL);
+        assert_eq!(release.tracks.len(), 3);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /release/7d7a9a5f-2c7e-4a3e-9a57-8a1c2b3d4e5f?"));
+        assert!(requests[0].contains("inc=recordings%2Bartist-credits"));
+        assert!(requests[0].contains("fmt=json"));
+    }
+
+    #[test]
+    fn release_search_parses() {
+        let releases = parse_release_search(RELEASE_SEARCH).unwrap();
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].id, "f5093c06-23e3-404f-aeaa-40f72885ee3a");
+        assert_eq!(releases[0].title, "Abbey Road");
+        assert_eq!(releases[0].artist, "The Beatles");
+        assert_eq!(releases[0].year, "1969");
+        assert_eq!(releases[0].track_count, 17);
+        // search results come without tracklists
+        assert!(releases[0].tracks.is_empty());
+
+        assert_eq!(releases[1].artist, "Artist One & Artist Two");
+        assert_eq!(releases[1].year, "");
+        assert_eq!(releases[1].track_count, 18);
+    }
+
+    #[test]
+    fn recording_search_parses_one_release_per_appearance() {
+        let releases = parse_recording_search(RECORDING_SEARCH).unwrap();
+        assert_eq!(releases.len(), 2);
+        assert_eq!(releases[0].title, "Abbey Road");
+        assert_eq!(releases[0].year, "1969");
+        assert_eq!(releases[1].title, "1");
+        assert_eq!(releases[1].year, "");
+        for release in &releases {
+            assert_eq!(release.artist, "The Beatles");
+            assert_eq!(release.tracks.len(), 1);
+            assert_eq!(release.tracks[0].title, "Come Together");
+            assert_eq!(release.tracks[0].recording_id, "b3a3c3b6-0e6f-4d8e-9d9f-6a1f3f0b2c11");
+            assert_eq!(release.tracks[0].position, 1);
+        }
+    }
+
+    #[test]
+    fn searches_without_results_are_errors() {
+        assert!(parse_release_search(r#"{"error": "Invalid query"}"#).is_err());
+        assert!(parse_recording_search("not json").is_err());
+    }
+}
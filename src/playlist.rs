@@ -1,9 +1,16 @@
 use eframe::egui;
+use std::collections::HashSet;
 use std::io::Write;
-use std::path::PathBuf;
-use std::fs::File;
-use crate::{UrlInfo, Single, Renderable, Downloadable};
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use crate::{UrlInfo, Single, Renderable, Downloadable, Appendable};
+use crate::single::{self, FullVideos, SongEdit};
+use crate::rules::RuleEditor;
 use crate::utils;
+use crate::library::Index;
+use crate::details::Details;
+use crate::loudness;
+use crate::settings::Settings;
 
 extern crate sanitize_filename;
 
@@ -13,18 +20,26 @@ pub struct Playlist {
     pub songs: Vec<Single>,
 
     pub use_thumbnail: bool,
-    pub cover_path: Option<PathBuf>
+    pub cover_path: Option<PathBuf>,
+
+    pub rules: RuleEditor,
+    /// Keep songs in the library and only write the m3u8 to the playlist folder.
+    pub reference_library: bool,
+    pub edit: Option<SongEdit>,
+    pub details: Details
 }
 
 impl From<Vec<UrlInfo>> for Playlist {
     fn from(urls: Vec<UrlInfo>) -> Self {
         let mut playlist = Playlist::default();
         playlist.use_thumbnail = true;
+        playlist.rules = RuleEditor::load();
         if let Some(title) = &urls[0].playlist {
             playlist.playlist_title = title.to_string();
         } else {
             playlist.playlist_title = "Playlist".to_string();
         }
+        playlist.details.queue(&urls);
         for url in urls {
             let song = Single::from(url);
             playlist.songs.push(song);
@@ -33,8 +48,16 @@ impl From<Vec<UrlInfo>> for Playlist {
     }
 }
 
+impl Appendable for Playlist {
+    fn append(&mut self, urls: Vec<UrlInfo>, split_chapters: bool) -> bool {
+        self.details.queue(&urls);
+        self.songs.extend(single::songs_from(urls, split_chapters));
+        return true
+    }
+}
+
 impl Renderable for Playlist {
-    fn render(&mut self, ui: &mut egui::Ui) {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
         ui.text_edit_singleline(&mut self.playlist_title);
         ui.horizontal(|ui| {
             ui.checkbox(&mut self.use_thumbnail, "Use Thumbnail");
@@ -68,47 +91,102 @@ impl Renderable for Playlist {
                 }
             }
         });
+        ui.checkbox(&mut self.reference_library, "Reference Library")
+            .on_hover_text("Songs already downloaded are linked from the playlist, new ones go to singles");
+        self.rules.render(ui, "playlist", &mut self.songs);
+        single::render_selection(ui, &mut self.songs);
+        self.details.render(ui, &mut self.songs);
         ui.separator();
-        for song in &mut self.songs {
-            song.render(ui);
+        let len = self.songs.len();
+        for (i, song) in self.songs.iter_mut().enumerate() {
+            SongEdit::render(ui, i, len, &mut self.edit);
+            ui.checkbox(&mut song.include, "Include");
+            song.render(ui, settings);
             ui.separator();
         }
+        if let Some(edit) = self.edit.take() {
+            edit.apply(&mut self.songs);
+        }
     }
 }
 
 impl Downloadable for Playlist {
-    fn download(&self, base_dir: &PathBuf) {
-        let download_dir = base_dir.join("playlists").join(sanitize_filename::sanitize(&self.playlist_title));
-        let mut file_string = String::default();
-
-        for song in &self.songs {
-            let output_format = format!("{}---{}.%(ext)s", sanitize_filename::sanitize(&song.track), sanitize_filename::sanitize(&song.artist));
-            utils::download_video(&song.webpage_url, &output_format, download_dir.to_str().unwrap(), song.use_thumbnail);
+    fn ready(&self) -> bool {
+        !self.details.is_loading()
+    }
 
-            let mp3_name = output_format.replace("%(ext)s", "mp3");
-            let mp3_path = &download_dir.join(mp3_name);
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        let mut errors = Vec::new();
+        let playlist_dir = base_dir.join("playlists").join(utils::sanitize_or(&self.playlist_title, "Playlist"));
+        if let Err(e) = fs::create_dir_all(&playlist_dir) {
+            errors.push(format!("{}: {}", playlist_dir.display(), e));
+            return errors
+        }
+        // referenced playlists are just the m3u8, new songs are stored like singles
+        let flat_dir = if self.reference_library { base_dir.join("singles") } else { playlist_dir.to_path_buf() };
+        let mut index = if self.reference_library { Some(Index::build(base_dir)) } else { None };
 
-            file_string.push_str(mp3_path.to_str().unwrap());
-            file_string.push('\n');
+        let job = utils::Job::new(base_dir);
+        let mut full_videos = FullVideos::new();
+        let mut file_string = String::default();
+        let mut kept = Vec::new();
+        let mut taken = if self.reference_library { utils::existing_stems(&flat_dir) } else { HashSet::new() };
+
+        for song in self.songs.iter().filter(|song| song.include) {
+            // clips share their video's id with the rest of it
+            let video_id = if song.is_clip() { "" } else { song.video_id.as_str() };
+            if let Some(path) = index.as_ref().and_then(|index| index.find(video_id, &song.full_artist(), &song.track)) {
+                add_entry(&mut file_string, path, &mut errors);
+                continue;
+            }
 
-            if let Some(path) = &song.cover_path {
-                let cover_name = path.file_name().unwrap();
-                let new_path = &download_dir.join(cover_name);
-                let _ = std::fs::copy(path, new_path);
-
-                let cover_path = utils::convert_jpg(&new_path);
-                song.tag(mp3_path, &cover_path, None);
-            } else if song.use_thumbnail {
-                let cover_name = output_format.replace("%(ext)s", "webp");
-                let cover_path = &download_dir.join(cover_name);
-                let cover_path = utils::convert_jpg(&cover_path);
-                song.tag(mp3_path, &cover_path, None);
+            let stem = utils::unique_stem(utils::song_stem(&song.track, &song.artist), &mut taken);
+            let mp3_path = match song.download_audio(&job.dir, &stem, settings, &mut full_videos, &mut errors) {
+                Some(mp3_path) => mp3_path,
+                None => continue
+            };
+
+            let cover_path = song.prepare_cover(&job.dir, &stem, &settings.cover).unwrap_or_else(|e| {
+                errors.push(song.problem(e));
+                None
+            });
+            let steps = [
+                song.tag(&mp3_path, cover_path.as_ref(), None, None),
+                song.tag_subtitles(&mp3_path, &job.dir, &stem)
+            ];
+            errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| song.problem(e)));
+            let dest = settings.layout.song_path(base_dir, &flat_dir, &stem, &song.place("", None));
+            let mp3_path = match job.keep(&mp3_path, &dest) {
+                Ok(mp3_path) => mp3_path,
+                Err(e) => {
+                    errors.push(song.problem(e));
+                    continue;
+                }
+            };
+            // listed twice, the second one references this download
+            if let Some(index) = &mut index {
+                index.add(video_id, &song.full_artist(), &song.track, &mp3_path);
             }
+            add_entry(&mut file_string, &mp3_path, &mut errors);
+            kept.push(mp3_path);
         }
+        errors.extend(loudness::tag_replaygain(&kept, false, &settings.loudness));
 
-        let file_path = download_dir.join(format!("{}.m3u8", sanitize_filename::sanitize(&self.playlist_title)));
-        let mut file = File::create(file_path).unwrap();
-        let _ = file.write_all(file_string.as_bytes());
-        utils::cleanup(&download_dir);
+        let file_path = playlist_dir.join(format!("{}.m3u8", utils::sanitize_or(&self.playlist_title, "Playlist")));
+        if let Err(e) = File::create(&file_path).and_then(|mut file| file.write_all(file_string.as_bytes())) {
+            errors.push(format!("{}: {}", file_path.display(), e));
+        }
+        return errors
+    }
+}
+
+/// Adds `path` as a line of the m3u8, the file is left out if its path isn't UTF-8.
+fn add_entry(file_string: &mut String, path: &Path, errors: &mut Vec<String>) {
+    match path.to_str() {
+        Some(path) => {
+            file_string.push_str(path);
+            file_string.push('\n');
+        },
+        None => errors.push(format!("{}: not a UTF-8 path, left out of the playlist", path.display()))
     }
 }
@@ -0,0 +1,201 @@
+use eframe::egui;
+use image::DynamicImage;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use crate::cover::{self, CoverOptions};
+
+// longest side of the preview textures, covers are shown small so there's no need to keep the full image
+const TEXTURE_SIZE: u32 = 512;
+
+/// A cover image loaded in the background from a file path or an http(s) url.
+/// For an mp3 path the embedded cover is shown.
+#[derive(Default)]
+pub struct CoverPreview {
+    source: String,
+    texture: Option<egui::TextureHandle>,
+    /// Full size image, the cover options are applied to it for [`CoverPreview::show_embedded`].
+    image: Option<DynamicImage>,
+    /// Options the embedded texture was made with, and its size before scaling it down for display.
+    embedded: Option<(CoverOptions, egui::TextureHandle, [u32; 2])>,
+    pending: Option<Receiver<Result<(DynamicImage, egui::ColorImage), String>>>,
+    error: Option<String>
+}
+
+impl CoverPreview {
+    /// Preview source for a song or album, the picked image if there is one, the thumbnail otherwise.
+    pub fn source_for(use_thumbnail: bool, cover_path: &Option<PathBuf>, thumbnail: &str) -> String {
+        match cover_path {
+            Some(path) => path.to_string_lossy().to_string(),
+            None if use_thumbnail => thumbnail.to_string(),
+            None => String::default()
+        }
+    }
+
+    /// Shows `source` in a `size` square, returns the clickable response.
+    pub fn show(&mut self, ui: &mut egui::Ui, source: &str, size: f32) -> egui::Response {
+        self.poll(ui.ctx(), source);
+
+        let size = egui::vec2(size, size);
+        if let Some(texture) = &self.texture {
+            ui.add(egui::Image::from_texture(texture)
+                .max_size(size)
+                .sense(egui::Sense::click()))
+        } else {
+            let text = if self.pending.is_some() {
+                "Loading"
+            } else if self.error.is_some() {
+                "No Preview"
+            } else {
+                "No Cover"
+            };
+            let response = ui.add_sized(size, egui::Button::new(egui::RichText::new(text).weak()).frame(true));
+            match &self.error {
+                Some(e) => response.on_hover_text(e),
+                None => response
+            }
+        }
+    }
+
+    /// Same image as it gets embedded with `options`, with its final size below it.
+    pub fn show_embedded(&mut self, ui: &mut egui::Ui, options: &CoverOptions, size: f32) {
+        let image = match &self.image {
+            Some(image) => image,
+            None => return
+        };
+        if !self.embedded.as_ref().is_some_and(|(made_with, _, _)| made_with == options) {
+            let embedded = cover::transform(image.clone(), options);
+            let dimensions = [embedded.width(), embedded.height()];
+            let texture = ui.ctx().load_texture(format!("{}-embedded", self.source), color_image(&embedded), Default::default());
+            self.embedded = Some((options.clone(), texture, dimensions));
+        }
+
+        if let Some((_, texture, [width, height])) = &self.embedded {
+            ui.add(egui::Image::from_texture(texture).max_size(egui::vec2(size, size)));
+            ui.weak(format!("{} × {} px", width, height));
+        }
+    }
+
+    fn poll(&mut self, ctx: &egui::Context, source: &str) {
+        if source != self.source {
+            self.source = source.to_string();
+            self.texture = None;
+            self.image = None;
+            self.embedded = None;
+            self.error = None;
+            self.pending = None;
+            if !source.is_empty() {
+                self.pending = Some(load(ctx, source.to_string()));
+            }
+        }
+
+        if let Some(pending) = &self.pending {
+            if let Ok(result) = pending.try_recv() {
+                match result {
+                    Ok((image, texture)) => {
+                        self.texture = Some(ctx.load_texture(&self.source, texture, Default::default()));
+                        self.image = Some(image);
+                    },
+                    Err(e) => self.error = Some(e)
+                }
+                self.pending = None;
+            }
+        }
+    }
+}
+
+fn load(ctx: &egui::Context, source: String) -> Receiver<Result<(DynamicImage, egui::ColorImage), String>> {
+    let (sender, receiver) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        let _ = sender.send(read_image(&source));
+        ctx.request_repaint();
+    });
+    return receiver
+}
+
+fn read_image(source: &str) -> Result<(DynamicImage, egui::ColorImage), String> {
+    let bytes = if source.starts_with("http://") || source.starts_with("https://") {
+        let mut bytes = Vec::new();
+        ureq::get(source).call()
+            .map_err(|e| e.to_string())?
+            .into_reader()
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        bytes
+    } else if source.to_lowercase().ends_with(".mp3") {
+        // local files show their embedded cover
+        id3::Tag::read_from_path(source)
+            .map_err(|e| e.to_string())?
+            .pictures()
+            .next()
+            .map(|picture| picture.data.to_vec())
+            .ok_or("No embedded cover")?
+    } else {
+        std::fs::read(source).map_err(|e| e.to_string())?
+    };
+
+    let image = image::load_from_memory(&bytes).map_err(|e| e.to_string())?;
+    let texture = color_image(&image);
+    Ok((image, texture))
+}
+
+/// `image` scaled down to the texture size.
+fn color_image(image: &DynamicImage) -> egui::ColorImage {
+    let image = image.thumbnail(TEXTURE_SIZE, TEXTURE_SIZE).to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    egui::ColorImage::from_rgba_unmultiplied(size, image.as_flat_samples().as_slice())
+}
+
+/// Window showing a cover next to what gets embedded with the cover options in Settings,
+/// edits `use_thumbnail` and `cover_path` directly.
+#[allow(clippy::too_many_arguments)]
+pub fn cover_dialog(
+    ctx: &egui::Context,
+    id: egui::Id,
+    title: &str,
+    open: &mut bool,
+    preview: &mut CoverPreview,
+    options: &CoverOptions,
+    use_thumbnail: &mut bool,
+    cover_path: &mut Option<PathBuf>
+) {
+    egui::Window::new(format!("Cover: {}", title))
+        .id(id)
+        .open(open)
+        .collapsible(false)
+        .show(ctx, |ui| {
+            // covers of local files are only processed when another image is picked
+            let is_embedded = preview.source.to_lowercase().ends_with(".mp3");
+            let options = if is_embedded { CoverOptions::default() } else { options.clone() };
+            ui.horizontal(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Original");
+                    let source = preview.source.to_string();
+                    preview.show(ui, &source, 256.0);
+                });
+                ui.vertical(|ui| {
+                    ui.label("Embedded");
+                    preview.show_embedded(ui, &options, 256.0);
+                });
+            });
+            ui.weak("Letterbox trimming, square crop and max size are set in Settings.");
+
+            ui.horizontal(|ui| {
+                if ui.button("Replace Image").clicked() {
+                    let fd = rfd::FileDialog::new()
+                        .add_filter("image", &["png", "jpg", "jpeg", "webp"]);
+
+                    if let Some(path) = fd.pick_file() {
+                        *use_thumbnail = false;
+                        *cover_path = Some(path);
+                    }
+                }
+                if ui.button("Use Thumbnail").clicked() {
+                    *use_thumbnail = true;
+                    *cover_path = None;
+                }
+            });
+        });
+}
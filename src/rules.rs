@@ -0,0 +1,354 @@
+use eframe::egui;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use crate::Single;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Rule {
+    pub enabled: bool,
+    pub pattern: String,
+    pub replacement: String
+}
+
+impl Rule {
+    fn new(pattern: &str, replacement: &str) -> Self {
+        Rule {
+            enabled: true,
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string()
+        }
+    }
+}
+
+pub struct Preset {
+    pub name: &'static str,
+    pub rules: &'static [(&'static str, &'static str)]
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "Video Junk",
+        rules: &[
+            (r"(?i)\s*[(\[](official\s+)?(music\s+|lyric\s+|audio\s+)?(video|audio|visuali[sz]er|lyrics?)[)\]]", ""),
+            (r"(?i)\s*[(\[](hd|hq|4k|1080p|720p|remastered)[)\]]", ""),
+        ]
+    },
+    Preset {
+        name: "Featuring",
+        rules: &[
+            (r"(?i)\b(ft\.?|feat\.?|featuring)\s+", "feat. "),
+        ]
+    },
+    Preset {
+        name: "Artist Prefix",
+        rules: &[
+            (r"^[^-–]+\s[-–]\s", ""),
+        ]
+    },
+    Preset {
+        name: "Whitespace",
+        rules: &[
+            (r"\s{2,}", " "),
+            (r"^\s+|\s+$", ""),
+        ]
+    },
+];
+
+/// Runs the compiled rules over `text` in order.
+pub fn apply(rules: &[(Regex, String)], text: &str) -> String {
+    let mut text = text.to_string();
+    for (regex, replacement) in rules {
+        text = regex.replace_all(&text, replacement.as_str()).into_owned();
+    }
+    return text
+}
+
+
+/// Text the rules run on, the track if one is set, the raw video title otherwise.
+fn source(song: &Single) -> &str {
+    if song.track.is_empty() {
+        &song.title
+    } else {
+        &song.track
+    }
+}
+
+fn rules_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("bebops").join("title_rules.json"))
+}
+
+pub fn read_rules(path: &PathBuf) -> Result<Vec<Rule>, String> {
+    let json = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&json).map_err(|e| e.to_string())
+}
+
+pub fn write_rules(path: &PathBuf, rules: &[Rule]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_string_pretty(rules).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| e.to_string())
+}
+
+#[derive(Default)]
+pub struct RuleEditor {
+    pub rules: Vec<Rule>,
+    pub status: String,
+
+    /// The rules as they were last compiled, to notice edits.
+    compiled_rules: Vec<Rule>,
+    /// Enabled rules that compiled.
+    compiled: Vec<(Regex, String)>,
+    /// Compile error of each rule.
+    invalid: Vec<Option<String>>,
+    moved: Option<(usize, usize)>,
+    marked: Option<usize>
+}
+
+impl RuleEditor {
+    /// Editor with the rules saved from a previous session, if any.
+    pub fn load() -> Self {
+        let mut editor = RuleEditor::default();
+        if let Some(path) = rules_path() {
+            if let Ok(rules) = read_rules(&path) {
+                editor.rules = rules;
+            }
+        }
+        return editor
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui, id: &str, songs: &mut [Single]) {
+        egui::CollapsingHeader::new("Title Cleanup")
+            .id_source(format!("{id}-title-rules"))
+            .show(ui, |ui| {
+                self.compile();
+                self.render_rules(ui);
+                self.render_controls(ui);
+                ui.separator();
+                self.render_preview(ui, id, songs);
+            });
+
+        self.apply_edits();
+    }
+
+    fn render_rules(&mut self, ui: &mut egui::Ui) {
+        let count = self.rules.len();
+        for (i, rule) in self.rules.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let mut changed = ui.checkbox(&mut rule.enabled, "").changed();
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.pattern).code_editor().hint_text("pattern")).changed();
+                ui.label("→");
+                changed |= ui.add(egui::TextEdit::singleline(&mut rule.replacement).code_editor().hint_text("replacement")).changed();
+                // the rules are compiled again at the start of the next frame
+                if changed {
+                    ui.ctx().request_repaint();
+                }
+
+                if ui.add_enabled(i > 0, egui::Button::new("⏶")).clicked() {
+                    self.moved = Some((i, i - 1));
+                }
+                if ui.add_enabled(i + 1 < count, egui::Button::new("⏷")).clicked() {
+                    self.moved = Some((i, i + 1));
+                }
+                if ui.button("Remove").clicked() {
+                    self.marked = Some(i);
+                }
+
+                if let Some(Some(e)) = self.invalid.get(i) {
+                    ui.colored_label(ui.visuals().error_fg_color, "invalid")
+                        .on_hover_text(e);
+                }
+            });
+        }
+    }
+
+    fn render_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Add Rule").clicked() {
+                self.rules.push(Rule::new("", ""));
+            }
+
+            ui.menu_button("Add Preset", |ui| {
+                for preset in PRESETS {
+                    if ui.button(preset.name).clicked() {
+                        for (pattern, replacement) in preset.rules {
+                            self.rules.push(Rule::new(pattern, replacement));
+                        }
+                        ui.close_menu();
+                    }
+                }
+            });
+
+            if ui.button("Save").clicked() {
+                self.status = match rules_path() {
+                    Some(path) => match write_rules(&path, &self.rules) {
+                        Ok(()) => "Saved".to_string(),
+                        Err(e) => e
+                    },
+                    None => "No config directory".to_string()
+                };
+            }
+
+            if ui.button("Import").clicked() {
+                let fd = rfd::FileDialog::new()
+                    .add_filter("json", &["json"]);
+
+                if let Some(path) = fd.pick_file() {
+                    self.status = match read_rules(&path) {
+                        Ok(rules) => {
+                            self.rules = rules;
+                            "Imported".to_string()
+                        },
+                        Err(e) => e
+                    };
+                }
+            }
+
+            if ui.button("Export").clicked() {
+                let fd = rfd::FileDialog::new()
+                    .add_filter("json", &["json"])
+                    .set_file_name("title_rules.json");
+
+                if let Some(path) = fd.save_file() {
+                    self.status = match write_rules(&path, &self.rules) {
+                        Ok(()) => "Exported".to_string(),
+                        Err(e) => e
+                    };
+                }
+            }
+
+            ui.label(&self.status);
+        });
+    }
+
+    /// Compiles the rules again if they changed since last time, invalid patterns are skipped.
+    fn compile(&mut self) {
+        if self.compiled_rules == self.rules {
+            return
+        }
+
+        self.compiled = Vec::new();
+        self.invalid = Vec::new();
+        for rule in &self.rules {
+            match Regex::new(&rule.pattern) {
+                Ok(regex) => {
+                    if rule.enabled {
+                        self.compiled.push((regex, rule.replacement.clone()));
+                    }
+                    self.invalid.push(None);
+                },
+                Err(e) => self.invalid.push(Some(e.to_string()))
+            }
+        }
+        self.compiled_rules = self.rules.clone();
+    }
+
+    fn render_preview(&mut self, ui: &mut egui::Ui, id: &str, songs: &mut [Single]) {
+        // picks up edits made above in this frame
+        self.compile();
+        let changes: Vec<(usize, String)> = songs.iter()
+            .enumerate()
+            .filter_map(|(i, song)| {
+                let cleaned = apply(&self.compiled, source(song));
+                (cleaned != song.track).then_some((i, cleaned))
+            })
+            .collect();
+
+        if changes.is_empty() {
+            ui.label("No titles would change");
+            return
+        }
+
+        egui::Grid::new(format!("{id}-title-rules-preview")).striped(true).show(ui, |ui| {
+            for (i, cleaned) in &changes {
+                ui.label(egui::RichText::new(source(&songs[*i])).strikethrough().weak());
+                ui.label("→");
+                ui.label(cleaned);
+                ui.end_row();
+            }
+        });
+
+        if ui.button(format!("Apply to {} Songs", changes.len())).clicked() {
+            for (i, cleaned) in changes {
+                songs[i].track = cleaned;
+                // cleaned up by hand now, details loaded later mustn't replace it
+                songs[i].inferred.track = false;
+            }
+        }
+    }
+
+    fn apply_edits(&mut self) {
+        if let Some((from, to)) = self.moved.take() {
+            self.rules.swap(from, to);
+        }
+        if let Some(i) = self.marked.take() {
+            self.rules.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor(rules: &[(&str, &str)]) -> RuleEditor {
+        let mut editor = RuleEditor::default();
+        editor.rules = rules.iter().map(|(pattern, replacement)| Rule::new(pattern, replacement)).collect();
+        editor.compile();
+        return editor
+    }
+
+    fn preset(name: &str) -> RuleEditor {
+        editor(PRESETS.iter().find(|preset| preset.name == name).unwrap().rules)
+    }
+
+    #[test]
+    fn video_junk_is_removed() {
+        let editor = preset("Video Junk");
+        assert_eq!(apply(&editor.compiled, "Song (Official Music Video)"), "Song");
+        assert_eq!(apply(&editor.compiled, "Song [Lyrics]"), "Song");
+        assert_eq!(apply(&editor.compiled, "Song (Visualizer) [HD]"), "Song");
+        assert_eq!(apply(&editor.compiled, "Song (Live)"), "Song (Live)");
+    }
+
+    #[test]
+    fn featuring_is_spelled_one_way() {
+        let editor = preset("Featuring");
+        assert_eq!(apply(&editor.compiled, "Song ft. Guest"), "Song feat. Guest");
+        assert_eq!(apply(&editor.compiled, "Song (Featuring Guest)"), "Song (feat. Guest)");
+        assert_eq!(apply(&editor.compiled, "Song feat. Guest"), "Song feat. Guest");
+        assert_eq!(apply(&editor.compiled, "Aftermath"), "Aftermath");
+    }
+
+    #[test]
+    fn artist_prefixes_are_removed() {
+        let editor = preset("Artist Prefix");
+        assert_eq!(apply(&editor.compiled, "Artist - Song"), "Song");
+        assert_eq!(apply(&editor.compiled, "Artist – Song"), "Song");
+        assert_eq!(apply(&editor.compiled, "Song"), "Song");
+        assert_eq!(apply(&editor.compiled, "Hyphen-ated"), "Hyphen-ated");
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        let editor = preset("Whitespace");
+        assert_eq!(apply(&editor.compiled, "  Song   Title "), "Song Title");
+    }
+
+    #[test]
+    fn replacements_use_capture_groups() {
+        let editor = editor(&[(r"^(.+) \| (.+)$", "$2 ($1)"), (r"(?i)\bprod\. (\w+)", "prod. by ${1}")]);
+        assert_eq!(apply(&editor.compiled, "Song | Artist prod. Someone"), "Artist prod. by Someone (Song)");
+    }
+
+    #[test]
+    fn invalid_and_disabled_rules_are_skipped() {
+        let mut editor = editor(&[("(", ""), ("a", "b"), ("c", "d")]);
+        editor.rules[2].enabled = false;
+        editor.compile();
+        assert!(editor.invalid[0].is_some());
+        assert_eq!(apply(&editor.compiled, "ac"), "bc");
+    }
+}
@@ -0,0 +1,235 @@
+use eframe::egui;
+use serde::Deserialize;
+use std::process::Command;
+use crate::DownloadType;
+use crate::preview::CoverPreview;
+use crate::utils::{self, Task};
+
+/// A video from `yt-dlp --flat-playlist`, only what the site's search page lists.
+#[derive(Deserialize)]
+pub struct SearchResult {
+    pub id: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    pub url: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    pub title: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    pub channel: String,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    pub uploader: String,
+    pub duration: Option<f32>,
+    #[serde(default, deserialize_with = "utils::null_default")]
+    pub thumbnails: Vec<Thumbnail>
+}
+
+#[derive(Deserialize)]
+pub struct Thumbnail {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>
+}
+
+/// Biggest of `thumbnails`. Sizes aren't always given, without them the last one is taken,
+/// yt-dlp lists thumbnails from worst to best.
+pub fn best_thumbnail(thumbnails: &[Thumbnail]) -> Option<&Thumbnail> {
+    thumbnails.iter()
+        .enumerate()
+        .max_by_key(|(i, thumbnail)| (thumbnail.width.unwrap_or_default() * thumbnail.height.unwrap_or_default(), *i))
+        .map(|(_, thumbnail)| thumbnail)
+}
+
+impl SearchResult {
+    pub fn webpage_url(&self) -> String {
+        if self.url.starts_with("http") {
+            self.url.to_string()
+        } else {
+            format!("https://www.youtube.com/watch?v={}", self.id)
+        }
+    }
+
+    pub fn channel(&self) -> &str {
+        if self.channel.is_empty() { &self.uploader } else { &self.channel }
+    }
+
+    pub fn thumbnail(&self) -> &str {
+        best_thumbnail(&self.thumbnails).map(|thumbnail| thumbnail.url.as_str()).unwrap_or_default()
+    }
+}
+
+/// What to do with a picked result.
+pub enum Pick {
+    Fetch(String, DownloadType),
+    Append(String, DownloadType)
+}
+
+/// YouTube search through `yt-dlp "ytsearchN:query"`.
+pub struct Search {
+    pub query: String,
+    pub count: usize,
+    pub download_type: DownloadType,
+    pub results: Vec<SearchResult>,
+    previews: Vec<CoverPreview>,
+    pub status: String,
+    pending: Option<Task<Vec<SearchResult>>>
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            query: String::default(),
+            count: 10,
+            download_type: DownloadType::default(),
+            results: Vec::new(),
+            previews: Vec::new(),
+            status: String::default(),
+            pending: None
+        }
+    }
+}
+
+impl Search {
+    /// Starts the search in the background, the results replace the current ones once they are in.
+    pub fn run(&mut self, ctx: &egui::Context) {
+        if self.query.trim().is_empty() {
+            return
+        }
+        let (query, count) = (self.query.trim().to_string(), self.count);
+        self.pending = Some(Task::spawn(ctx, move || search(&query, count)));
+        self.status = "Searching".to_string();
+    }
+
+    fn poll(&mut self) {
+        let results = match self.pending.as_ref().and_then(|task| task.poll()) {
+            Some(results) => results,
+            None => return
+        };
+        self.pending = None;
+
+        match results {
+            Ok(results) => {
+                self.status = format!("{} results", results.len());
+                self.previews = results.iter().map(|_| CoverPreview::default()).collect();
+                self.results = results;
+            },
+            Err(e) => self.status = e
+        }
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) -> Option<Pick> {
+        let mut pick = None;
+        self.poll();
+
+        ui.horizontal(|ui| {
+            let query = ui.text_edit_singleline(&mut self.query);
+            ui.add(egui::DragValue::new(&mut self.count).clamp_range(1..=50).suffix(" results"));
+            if ui.button("Search").clicked() || (query.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) {
+                self.run(ui.ctx());
+            }
+            if self.pending.is_some() {
+                ui.spinner();
+            }
+            ui.label(&self.status);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Fetch as");
+            self.download_type.render(ui, "search-download-type");
+        });
+        ui.separator();
+
+        egui::ScrollArea::vertical().id_source("search-results").show(ui, |ui| {
+            for (result, preview) in self.results.iter().zip(&mut self.previews) {
+                ui.horizontal(|ui| {
+                    preview.show(ui, result.thumbnail(), 64.0);
+                    ui.vertical(|ui| {
+                        ui.strong(&result.title);
+                        ui.label(result.channel());
+                        if let Some(duration) = result.duration {
+                            ui.label(utils::format_time(duration));
+                        }
+                        ui.horizontal(|ui| {
+                            if ui.button("Fetch").clicked() {
+                                pick = Some(Pick::Fetch(result.webpage_url(), self.download_type));
+                            }
+                            if ui.button("Append").clicked() {
+                                pick = Some(Pick::Append(result.webpage_url(), self.download_type));
+                            }
+                        });
+                    });
+                });
+                ui.separator();
+            }
+        });
+
+        return pick
+    }
+}
+
+/// First `count` YouTube results for `query`, without resolving each video.
+pub fn search(query: &str, count: usize) -> Result<Vec<SearchResult>, String> {
+    let output = Command::new("yt-dlp")
+        .arg(format!("ytsearch{}:{}", count, query))
+        .args(["--flat-playlist", "-j"])
+        .output()
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+    }
+    return Ok(parse_results(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// One json object per line, as `-j` prints them.
+fn parse_results(output: &str) -> Vec<SearchResult> {
+    output.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `yt-dlp "ytsearch3:night drive" --flat-playlist -j`, trimmed down
+    const OUTPUT: &str = concat!(
+        r#"{"_type": "url", "ie_key": "Youtube", "id": "a1b2c3d4e5f", "url": "https://www.youtube.com/watch?v=a1b2c3d4e5f", "title": "Night Drive", "description": null, "duration": 245.0, "channel_id": "UC123", "channel": "Some Artist", "channel_url": "https://www.youtube.com/channel/UC123", "uploader": null, "thumbnails": [{"url": "https://i.ytimg.com/vi/a1b2c3d4e5f/hq720.jpg?sqp=small", "height": 202, "width": 360}, {"url": "https://i.ytimg.com/vi/a1b2c3d4e5f/hq720.jpg?sqp=big", "height": 404, "width": 720}], "view_count": 1234, "playlist": "night drive", "playlist_index": 1}"#, "\n",
+        r#"{"_type": "url", "ie_key": "Youtube", "id": "f5e4d3c2b1a", "title": "Night Drive (Live)", "duration": null, "channel": null, "uploader": "Uploader Name", "thumbnails": [{"url": "https://i.ytimg.com/vi/f5e4d3c2b1a/big.jpg", "height": 404, "width": 720}, {"url": "https://i.ytimg.com/vi/f5e4d3c2b1a/small.jpg", "height": 94, "width": 168}]}"#, "\n",
+        r#"{"_type": "url", "ie_key": "Youtube", "id": "0000000000a", "url": null, "title": null, "channel": "Channel", "thumbnails": null}"#, "\n",
+        "WARNING: not json\n"
+    );
+
+    #[test]
+    fn results_parse() {
+        let results = parse_results(OUTPUT);
+        assert_eq!(results.len(), 3);
+
+        assert_eq!(results[0].title, "Night Drive");
+        assert_eq!(results[0].channel(), "Some Artist");
+        assert_eq!(results[0].duration, Some(245.0));
+        assert_eq!(results[0].webpage_url(), "https://www.youtube.com/watch?v=a1b2c3d4e5f");
+    }
+
+    #[test]
+    fn null_and_missing_fields_fall_back() {
+        let results = parse_results(OUTPUT);
+        assert_eq!(results[1].channel(), "Uploader Name");
+        assert_eq!(results[1].duration, None);
+        // without a url the result is still a YouTube video
+        assert_eq!(results[1].webpage_url(), "https://www.youtube.com/watch?v=f5e4d3c2b1a");
+        assert_eq!(results[2].webpage_url(), "https://www.youtube.com/watch?v=0000000000a");
+        assert_eq!(results[2].title, "");
+        assert_eq!(results[2].thumbnail(), "");
+    }
+
+    #[test]
+    fn the_biggest_thumbnail_is_shown() {
+        let results = parse_results(OUTPUT);
+        assert_eq!(results[0].thumbnail(), "https://i.ytimg.com/vi/a1b2c3d4e5f/hq720.jpg?sqp=big");
+        // listed biggest first
+        assert_eq!(results[1].thumbnail(), "https://i.ytimg.com/vi/f5e4d3c2b1a/big.jpg");
+
+        let without_sizes = vec![
+            Thumbnail { url: "first".to_string(), width: None, height: None },
+            Thumbnail { url: "last".to_string(), width: None, height: None }
+        ];
+        assert_eq!(best_thumbnail(&without_sizes).map(|thumbnail| thumbnail.url.as_str()), Some("last"));
+    }
+}
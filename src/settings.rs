@@ -0,0 +1,74 @@
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use crate::cover::{CoverOptions, FolderArt};
+use crate::layout::Layout;
+use crate::loudness::LoudnessOptions;
+use crate::sponsorblock::SponsorBlockOptions;
+
+/// App wide options, saved as json in the config directory.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub layout: Layout,
+    pub cover: CoverOptions,
+    pub folder_art: FolderArt,
+    pub loudness: LoudnessOptions,
+    pub sponsorblock: SponsorBlockOptions,
+
+    #[serde(skip)]
+    pub status: String
+}
+
+fn settings_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("bebops").join("settings.json"))
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        settings_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("No config directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| e.to_string())
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Library");
+        self.layout.render(ui);
+        ui.label("Existing downloads can be moved to the new layout from the Library window.");
+
+        ui.separator();
+        ui.heading("Cover Art");
+        self.cover.render(ui);
+        self.folder_art.render(ui);
+
+        ui.separator();
+        ui.heading("Loudness");
+        self.loudness.render(ui);
+
+        ui.separator();
+        ui.heading("Segments");
+        self.sponsorblock.render(ui);
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                self.status = match self.save() {
+                    Ok(()) => "Saved".to_string(),
+                    Err(e) => e
+                };
+            }
+            ui.label(&self.status);
+        });
+    }
+}
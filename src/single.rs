@@ -1,46 +1,572 @@
 use eframe::egui;
 use id3::{Tag, TagLike, Version};
-use id3::frame::{Picture, PictureType};
-use std::path::PathBuf;
-use crate::{UrlInfo, Renderable, Downloadable};
-use crate::utils;
+use id3::frame::{Content, ExtendedText, Picture, PictureType};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use crate::{UrlInfo, Renderable, Downloadable, Appendable};
+use crate::utils::{self, Task};
+use crate::cover::{self, CoverOptions};
+use crate::extractor::Extractor;
+use crate::settings::Settings;
+use crate::title;
+use crate::metadata::{Lookup, Release, ReleaseTrack};
+use crate::preview::{self, CoverPreview};
+use crate::lyrics::{self, Lyrics, LyricsProvider, Lrclib};
+use crate::loudness;
+use crate::trim;
+use crate::layout::Place;
+use crate::sponsorblock::{self, SponsorBlockOptions};
 
 extern crate sanitize_filename;
 
-#[derive(Default)]
+// TXXX descriptions for where a song came from
+pub const SOURCE_URL: &str = "Source URL";
+pub const VIDEO_ID: &str = "Video ID";
+pub const UPLOADER: &str = "Uploader";
+pub const FETCH_DATE: &str = "Fetch Date";
+pub const MUSICBRAINZ_ALBUM_ID: &str = "MusicBrainz Album Id";
+pub const MUSICBRAINZ_RECORDING_ID: &str = "MusicBrainz Recording Id";
+const PROVENANCE: [&str; 6] = [SOURCE_URL, VIDEO_ID, UPLOADER, FETCH_DATE, MUSICBRAINZ_ALBUM_ID, MUSICBRAINZ_RECORDING_ID];
+
+// frames `tag` writes, everything else is carried over when re-tagging a local file
+const EDITED_FRAMES: [&str; 7] = ["TIT2", "TPE1", "TALB", "TYER", "TDRC", "USLT", "SYLT"];
+
+// numbers every song for its widget ids
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Full videos downloaded for clips during one download, by video id, so the chapters
+/// of a video share one download. Failed downloads are kept too, they aren't retried.
+pub type FullVideos = HashMap<String, Result<PathBuf, String>>;
+
 pub struct Single {
+    pub include: bool,
+    /// Set for files opened from disk, these are re-tagged in place instead of downloaded.
+    pub local_path: Option<PathBuf>,
     pub webpage_url: String,
     pub title: String,
+    pub thumbnail: String,
     pub use_thumbnail: bool,
     pub cover_path: Option<PathBuf>,
+    pub preview: CoverPreview,
+    pub show_cover_dialog: bool,
 
     pub artist: String,
     pub track: String,
     pub album: String,
+    pub featured: String,
+    pub year: String,
+    pub inferred: Inferred,
+
+    /// Seconds cut from the start, and where the song ends (0 keeps the rest).
+    pub trim_start: f32,
+    pub trim_end: f32,
+    pub duration: f32,
+    pub trim_status: String,
+    pub silence: Option<Task<(f32, f32)>>,
+
+    pub lyrics: String,
+    pub synced_lyrics: String,
+    pub use_subtitles: bool,
+    pub lyrics_provider: Arc<dyn LyricsProvider>,
+    pub lyrics_status: String,
+    pub lyrics_search: Option<Task<Lyrics>>,
+
+    pub video_id: String,
+    pub uploader: String,
+    pub fetch_date: String,
+    pub musicbrainz_release_id: String,
+    pub musicbrainz_recording_id: String,
+    pub lookup: Lookup,
 
     pub description: String,
+
+    /// Disc of a multi-disc album this song is on, from 1.
+    pub disc: usize,
+    /// Where the site puts the song on its album, to order albums by.
+    pub track_number: Option<usize>,
+
+    uid: usize
+}
+
+/// Track and disc number of a song within an album.
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub track: usize,
+    pub disc: usize,
+    pub disc_total: usize
+}
+
+/// Which fields were guessed from the video title rather than given by yt-dlp.
+#[derive(Default)]
+pub struct Inferred {
+    pub artist: bool,
+    pub track: bool,
+    pub featured: bool
+}
+
+impl Default for Single {
+    fn default() -> Self {
+        Single {
+            include: true,
+            local_path: None,
+            webpage_url: String::default(),
+            title: String::default(),
+            thumbnail: String::default(),
+            use_thumbnail: false,
+            cover_path: None,
+            preview: CoverPreview::default(),
+            show_cover_dialog: false,
+            artist: String::default(),
+            track: String::default(),
+            album: String::default(),
+            featured: String::default(),
+            year: String::default(),
+            inferred: Inferred::default(),
+            trim_start: 0.0,
+            trim_end: 0.0,
+            duration: 0.0,
+            trim_status: String::default(),
+            silence: None,
+            lyrics: String::default(),
+            synced_lyrics: String::default(),
+            use_subtitles: false,
+            lyrics_provider: Arc::new(Lrclib::default()),
+            lyrics_status: String::default(),
+            lyrics_search: None,
+            video_id: String::default(),
+            uploader: String::default(),
+            fetch_date: String::default(),
+            musicbrainz_release_id: String::default(),
+            musicbrainz_recording_id: String::default(),
+            lookup: Lookup::default(),
+            description: String::default(),
+            disc: 1,
+            track_number: None,
+            uid: NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        }
+    }
 }
 
 impl From<UrlInfo> for Single {
     fn from(url: UrlInfo) -> Self {
+        let extractor = Extractor::of(&url);
+        let uploader_artist = extractor.uploader_artist(&url);
         let mut single = Single::default();
+        single.year = extractor.year(&url).unwrap_or_default();
         single.webpage_url = url.webpage_url;
         single.use_thumbnail = true;
+        single.thumbnail = url.thumbnail;
         single.title = url.title;
         single.artist = url.artist;
         single.track = url.track;
         single.album = url.album;
         single.description = url.description;
+        single.duration = url.duration.unwrap_or_default();
+        single.video_id = url.id;
+        single.track_number = url.track_number;
+        single.uploader = if url.uploader.is_empty() { url.channel.to_string() } else { url.uploader.to_string() };
+        single.fetch_date = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+        if extractor.parses_titles() && (single.track.is_empty() || single.artist.is_empty()) {
+            single.infer_from_title();
+        }
+        if single.track.is_empty() {
+            single.track = single.title.to_string();
+            single.inferred.track = true;
+        }
+        if single.artist.is_empty() {
+            single.artist = uploader_artist;
+            single.inferred.artist = !single.artist.is_empty();
+        }
+        single.lookup.title = single.track.to_string();
+        single.lookup.artist = single.artist.to_string();
 
         return single
     }
 }
 
+impl Appendable for Single {}
+
 impl Renderable for Single {
-    fn render(&mut self, ui: &mut egui::Ui) {
+    fn render(&mut self, ui: &mut egui::Ui, settings: &Settings) {
         ui.label(&self.title);
 
+        self.render_cover(ui, &settings.cover);
+
+        ui.horizontal(|ui| {
+            ui.label("Title");
+            if ui.text_edit_singleline(&mut self.track).changed() {
+                self.inferred.track = false;
+            }
+            inferred_label(ui, self.inferred.track);
+        });
         ui.horizontal(|ui| {
+            ui.label("Artist");
+            if ui.text_edit_singleline(&mut self.artist).changed() {
+                self.inferred.artist = false;
+            }
+            inferred_label(ui, self.inferred.artist);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Featuring");
+            if ui.text_edit_singleline(&mut self.featured).changed() {
+                self.inferred.featured = false;
+            }
+            inferred_label(ui, self.inferred.featured);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Album");
+            ui.text_edit_singleline(&mut self.album)
+        });
+        ui.horizontal(|ui| {
+            ui.label("Year");
+            ui.text_edit_singleline(&mut self.year)
+        });
+        // local files are only re-tagged, the audio isn't touched
+        if self.local_path.is_none() {
+            ui.horizontal(|ui| {
+                ui.label("Trim");
+                ui.add(utils::time_edit(&mut self.trim_start));
+                ui.label("to");
+                ui.add(utils::time_edit(&mut self.trim_end));
+                if self.duration > 0.0 {
+                    self.trim_start = self.trim_start.min(self.duration);
+                    self.trim_end = self.trim_end.min(self.duration);
+                }
+                if self.trim_end == 0.0 {
+                    ui.weak("end");
+                }
+                if self.trim_end > 0.0 && self.trim_end <= self.trim_start {
+                    ui.colored_label(ui.visuals().error_fg_color, "end before start");
+                }
+                let detect = ui.add_enabled(self.silence.is_none(), egui::Button::new("Detect Silence"));
+                if detect.on_hover_text("Downloads the audio to find silent intros and outros").clicked() {
+                    self.detect_silence(ui.ctx());
+                }
+                self.poll_silence();
+                if self.silence.is_some() {
+                    ui.spinner();
+                }
+                ui.label(&self.trim_status);
+            });
+        }
+        self.render_lyrics(ui);
+        if let Some(release) = self.lookup.render_recording(ui, &self.id()) {
+            if let Some(track) = release.tracks.first() {
+                self.apply_release(&release, track);
+            }
+        }
+        egui::CollapsingHeader::new("Description")
+            .id_source(self.id())
+            .show(ui, |ui| {
+                ui.label(&self.description);
+            });
+    }
+}
+
+impl Downloadable for Single {
+    fn download(&self, base_dir: &PathBuf, settings: &Settings) -> Vec<String> {
+        let mut errors = Vec::new();
+        let job = utils::Job::new(base_dir);
+        let flat_dir = base_dir.join("singles");
+        // a song with the same name is likely another version, not this one
+        let stem = utils::unique_stem(utils::song_stem(&self.track, &self.artist), &mut utils::existing_stems(&flat_dir));
+        let mp3_path = match self.download_audio(&job.dir, &stem, settings, &mut FullVideos::new(), &mut errors) {
+            Some(mp3_path) => mp3_path,
+            None => return errors
+        };
+
+        let cover_path = self.prepare_cover(&job.dir, &stem, &settings.cover).unwrap_or_else(|e| {
+            errors.push(self.problem(e));
+            None
+        });
+        let steps = [
+            self.tag(&mp3_path, cover_path.as_ref(), None, None),
+            self.tag_subtitles(&mp3_path, &job.dir, &stem)
+        ];
+        errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| self.problem(e)));
+        let dest = settings.layout.song_path(base_dir, &flat_dir, &stem, &self.place("", None));
+        let mp3_path = match job.keep(&mp3_path, &dest) {
+            Ok(mp3_path) => mp3_path,
+            Err(e) => {
+                errors.push(self.problem(e));
+                return errors
+            }
+        };
+        errors.extend(loudness::tag_replaygain(&[mp3_path], false, &settings.loudness));
+        return errors
+    }
+}
+
+impl Single {
+    /// Reads an existing mp3's tag so it can be edited and written back with `retag`.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let tag = Tag::read_from_path(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut single = Single::default();
+        single.local_path = Some(path.to_path_buf());
+        single.title = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        single.track = tag.title().unwrap_or_default().to_string();
+        single.album = tag.album().unwrap_or_default().to_string();
+        single.year = tag.year().map(|year| year.to_string()).unwrap_or_default();
+
+        // undo `full_artist`
+        let artist = tag.artist().unwrap_or_default();
+        match artist.split_once(" feat. ") {
+            Some((artist, featured)) => {
+                single.artist = artist.to_string();
+                single.featured = featured.to_string();
+            },
+            None => single.artist = artist.to_string()
+        }
+
+        for text in tag.extended_texts() {
+            let value = text.value.to_string();
+            match text.description.as_str() {
+                SOURCE_URL => single.webpage_url = value,
+                VIDEO_ID => single.video_id = value,
+                UPLOADER => single.uploader = value,
+                FETCH_DATE => single.fetch_date = value,
+                MUSICBRAINZ_ALBUM_ID => single.musicbrainz_release_id = value,
+                MUSICBRAINZ_RECORDING_ID => single.musicbrainz_recording_id = value,
+                _ => {}
+            }
+        }
+
+        if let Some(lyrics) = tag.lyrics().next() {
+            single.lyrics = lyrics.text.to_string();
+        }
+        if let Some(synced) = tag.synchronised_lyrics().next() {
+            single.synced_lyrics = lyrics::to_lrc(&synced.content);
+        }
+
+        // the embedded cover stands in for the thumbnail, see `preview`
+        single.use_thumbnail = tag.pictures().next().is_some();
+        single.thumbnail = path.to_string_lossy().to_string();
+
+        single.lookup.title = single.track.to_string();
+        single.lookup.artist = single.artist.to_string();
+
+        return Ok(single)
+    }
+
+    /// Writes the edited fields back to `local_path`. Frames bebops doesn't edit (track number,
+    /// genre, ReplayGain...) are kept, and so is the embedded cover while `use_thumbnail` is on.
+    pub fn retag(&self, options: &CoverOptions) -> Result<(), String> {
+        let path = self.local_path.as_ref().ok_or("Not a local file")?;
+        let old = Tag::read_from_path(path).unwrap_or_default();
+
+        let job = utils::Job::new(&std::env::temp_dir());
+        let cover_path = if self.use_thumbnail { None } else { self.prepare_cover(&job.dir, "", options)? };
+        let mut tag = self.build_tag(cover_path.as_ref(), None, None)?;
+        for frame in old.frames() {
+            let edited = match frame.content() {
+                Content::ExtendedText(text) => PROVENANCE.contains(&text.description.as_str()),
+                Content::Picture(_) => !self.use_thumbnail,
+                _ => EDITED_FRAMES.contains(&frame.id())
+            };
+            if !edited {
+                tag.add_frame(frame.clone());
+            }
+        }
+        tag.write_to_path(path, Version::Id3v24).map_err(|e| format!("tag: {}", e))
+    }
+
+    /// Stable id for this song's widgets, neither the source url nor the title is unique,
+    /// the same video can be added twice.
+    fn id(&self) -> String {
+        format!("song-{}", self.uid)
+    }
+
+    /// Fills in what a flat fetch left out from `fresh`, the same video fully fetched.
+    /// Only empty or inferred fields are replaced, anything typed in is kept.
+    pub fn update_from(&mut self, fresh: &Single) {
+        if self.track.is_empty() || self.inferred.track {
+            self.track = fresh.track.to_string();
+            self.inferred.track = fresh.inferred.track;
+            self.lookup.title = fresh.lookup.title.to_string();
+        }
+        if self.artist.is_empty() || self.inferred.artist {
+            self.artist = fresh.artist.to_string();
+            self.inferred.artist = fresh.inferred.artist;
+            self.lookup.artist = fresh.lookup.artist.to_string();
+        }
+        if self.featured.is_empty() || self.inferred.featured {
+            self.featured = fresh.featured.to_string();
+            self.inferred.featured = fresh.inferred.featured;
+        }
+        if self.album.is_empty() {
+            self.album = fresh.album.to_string();
+        }
+        if self.year.is_empty() {
+            self.year = fresh.year.to_string();
+        }
+        if self.thumbnail.is_empty() {
+            self.thumbnail = fresh.thumbnail.to_string();
+        }
+        if self.description.is_empty() {
+            self.description = fresh.description.to_string();
+        }
+        if self.uploader.is_empty() {
+            self.uploader = fresh.uploader.to_string();
+        }
+        if self.duration <= 0.0 {
+            self.duration = fresh.duration;
+        }
+        if self.track_number.is_none() {
+            self.track_number = fresh.track_number;
+        }
+    }
+
+    /// Only part of the video is kept, like a chapter of it.
+    pub fn is_clip(&self) -> bool {
+        self.trim_start > 0.0 || self.trim_end > 0.0
+    }
+
+    /// Fills an empty track/artist by parsing shapes like "Artist - Title" out of the video title.
+    pub fn infer_from_title(&mut self) {
+        if let Some(parsed) = title::parse(&self.title, &self.uploader) {
+            if self.track.is_empty() {
+                self.track = parsed.track;
+                self.inferred.track = true;
+            }
+            if self.artist.is_empty() {
+                self.artist = parsed.artist;
+                self.inferred.artist = true;
+            }
+            if self.featured.is_empty() && !parsed.featured.is_empty() {
+                self.featured = parsed.featured.join(", ");
+                self.inferred.featured = true;
+            }
+        }
+    }
+
+    /// Fills the song from a metadata provider's release and one of its tracks.
+    pub fn apply_release(&mut self, release: &Release, track: &ReleaseTrack) {
+        self.track = track.title.to_string();
+        self.artist = track.artist.to_string();
+        self.featured = String::default();
+        self.album = release.title.to_string();
+        self.year = release.year.to_string();
+        self.musicbrainz_release_id = release.id.to_string();
+        self.musicbrainz_recording_id = track.recording_id.to_string();
+        self.inferred = Inferred::default();
+    }
+
+    /// Copies where the song came from, used for chapters split out of a full video.
+    pub fn inherit_source(&mut self, webpage_url: &str, video_id: &str, uploader: &str) {
+        self.webpage_url = webpage_url.to_string();
+        self.video_id = video_id.to_string();
+        self.uploader = uploader.to_string();
+        self.fetch_date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    }
+
+    fn detect_silence(&mut self, ctx: &egui::Context) {
+        let webpage_url = self.webpage_url.to_string();
+        self.trim_status = "Downloading".to_string();
+        self.silence = Some(Task::spawn(ctx, move || {
+            let job = utils::Job::new(&std::env::temp_dir());
+            utils::download_video(&webpage_url, "probe.%(ext)s", &job.dir, false, false, &[])?;
+            trim::detect_silence(&job.dir.join("probe.mp3"))
+        }));
+    }
+
+    fn poll_silence(&mut self) {
+        let found = match self.silence.as_ref().and_then(|task| task.poll()) {
+            Some(found) => found,
+            None => return
+        };
+        self.silence = None;
+
+        match found {
+            Ok((start, end)) => {
+                self.trim_start = start;
+                self.trim_end = end;
+                self.trim_status = if start == 0.0 && end == 0.0 {
+                    "No silence found".to_string()
+                } else {
+                    "Trim set".to_string()
+                };
+            },
+            Err(e) => self.trim_status = e
+        }
+    }
+
+    /// Whether yt-dlp cuts the segments while downloading. Not for clips, cutting before
+    /// trimming would move the trim points.
+    fn ytdlp_removes(&self, options: &SponsorBlockOptions) -> bool {
+        options.ytdlp_removes() && !self.is_clip()
+    }
+
+    /// Cuts the configured segments out of a trimmed download, unless yt-dlp already did.
+    pub fn remove_segments(&self, mp3_path: &Path, options: &SponsorBlockOptions) -> Result<(), String> {
+        if self.ytdlp_removes(options) {
+            return Ok(())
+        }
+
+        let segments = sponsorblock::segments(&self.video_id, options).map_err(|e| format!("segments: {}", e))?;
+        sponsorblock::remove(mp3_path, &segments, self.trim_start)
+    }
+
+    /// Downloads the song into `dir` as `stem.mp3`, then trims it, cuts segments and normalizes it.
+    /// Clips are cut from their video in `full_videos`, which is downloaded the first time it's needed.
+    /// Only a failed download gives no file, anything after that is added to `errors`.
+    pub fn download_audio(&self, dir: &Path, stem: &str, settings: &Settings, full_videos: &mut FullVideos, errors: &mut Vec<String>) -> Option<PathBuf> {
+        let mp3_path = dir.join(format!("{}.mp3", stem));
+        let downloaded = if self.is_clip() && !self.video_id.is_empty() {
+            self.cut_clip(dir, stem, full_videos)
+        } else {
+            let ytdlp_args = if self.ytdlp_removes(&settings.sponsorblock) { settings.sponsorblock.ytdlp_args() } else { Vec::new() };
+            utils::download_video(&self.webpage_url, &format!("{}.%(ext)s", stem), dir, self.use_thumbnail, self.use_subtitles, &ytdlp_args)
+                .and_then(|_| trim::trim(&mp3_path, self.trim_start, self.trim_end))
+        };
+        if let Err(e) = downloaded {
+            errors.push(self.problem(e));
+            return None
+        }
+
+        let steps = [
+            self.remove_segments(&mp3_path, &settings.sponsorblock),
+            loudness::normalize(&mp3_path, &settings.loudness)
+        ];
+        errors.extend(steps.into_iter().filter_map(|step| step.err()).map(|e| self.problem(e)));
+        return Some(mp3_path)
+    }
+
+    /// Cuts the clip out of its full video as `stem.mp3`, its thumbnail is copied next to it.
+    fn cut_clip(&self, dir: &Path, stem: &str, full_videos: &mut FullVideos) -> Result<(), String> {
+        let full_stem = format!("{}---FULL", utils::sanitize_or(&self.video_id, "video"));
+        let full_mp3_path = full_videos.entry(self.video_id.to_string())
+            .or_insert_with(|| {
+                // the thumbnail is kept for every clip, whether they use it is up to them
+                utils::download_video(&self.webpage_url, &format!("{}.%(ext)s", full_stem), dir, true, false, &[])
+                    .map(|_| dir.join(format!("{}.mp3", full_stem)))
+            })
+            .clone()?;
+
+        trim::extract(&full_mp3_path, &dir.join(format!("{}.mp3", stem)), self.trim_start, self.trim_end)?;
+        if let Some(thumbnail) = cover::find_thumbnail(dir, &full_stem) {
+            let extension = thumbnail.extension().unwrap_or_default().to_string_lossy().to_string();
+            let _ = std::fs::copy(&thumbnail, dir.join(format!("{}.{}", stem, extension)));
+        }
+        return Ok(())
+    }
+
+    /// `e` as one of the problems of a download, prefixed with the song.
+    pub fn problem(&self, e: impl std::fmt::Display) -> String {
+        format!("{}: {}", self.track, e)
+    }
+
+    /// Cover preview and picker, the preview opens the cover dialog.
+    pub fn render_cover(&mut self, ui: &mut egui::Ui, options: &CoverOptions) {
+        ui.horizontal(|ui| {
+            let source = CoverPreview::source_for(self.use_thumbnail, &self.cover_path, &self.thumbnail);
+            if self.preview.show(ui, &source, 64.0).clicked() {
+                self.show_cover_dialog = true;
+            }
+
             ui.checkbox(&mut self.use_thumbnail, "Use Thumbnail");
 
             if !self.use_thumbnail {
@@ -61,78 +587,447 @@ impl Renderable for Single {
                 self.cover_path = None;
             }
         });
+        preview::cover_dialog(
+            ui.ctx(),
+            egui::Id::new(("cover", self.id())),
+            &self.track,
+            &mut self.show_cover_dialog,
+            &mut self.preview,
+            options,
+            &mut self.use_thumbnail,
+            &mut self.cover_path
+        );
+    }
 
-        ui.horizontal(|ui| {
-            ui.label("Title");
-            ui.text_edit_singleline(&mut self.track)
-        });
-        ui.horizontal(|ui| {
-            ui.label("Artist");
-            ui.text_edit_singleline(&mut self.artist)
-        });
-        ui.horizontal(|ui| {
-            ui.label("Album");
-            ui.text_edit_singleline(&mut self.album)
-        });
-        egui::CollapsingHeader::new("Description")
-            .id_source(&self.webpage_url)
+    fn render_lyrics(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Lyrics")
+            .id_source(format!("{}-lyrics", self.id()))
             .show(ui, |ui| {
-                ui.label(&self.description);
+                ui.horizontal(|ui| {
+                    if ui.button("Load .lrc").clicked() {
+                        let fd = rfd::FileDialog::new()
+                            .add_filter("lrc", &["lrc", "txt"]);
+
+                        if let Some(path) = fd.pick_file() {
+                            match lyrics::read_lrc(&path) {
+                                Ok(loaded) => {
+                                    self.lyrics = loaded.plain;
+                                    self.synced_lyrics = loaded.synced;
+                                    self.lyrics_status = "Loaded".to_string();
+                                },
+                                Err(e) => self.lyrics_status = e
+                            }
+                        }
+                    }
+
+                    if ui.button(format!("Search {}", self.lyrics_provider.name())).clicked() {
+                        let provider = Arc::clone(&self.lyrics_provider);
+                        let (artist, track) = (self.artist.to_string(), self.track.to_string());
+                        self.lyrics_search = Some(Task::spawn(ui.ctx(), move || provider.lyrics(&artist, &track)));
+                        self.lyrics_status = String::default();
+                    }
+                    if let Some(found) = self.lyrics_search.as_ref().and_then(|task| task.poll()) {
+                        self.lyrics_search = None;
+                        match found {
+                            Ok(found) => {
+                                self.lyrics = found.plain;
+                                self.synced_lyrics = found.synced;
+                                self.lyrics_status = "Found".to_string();
+                            },
+                            Err(e) => self.lyrics_status = e
+                        }
+                    }
+                    if self.lyrics_search.is_some() {
+                        ui.spinner();
+                    }
+
+                    if self.local_path.is_none() {
+                        ui.checkbox(&mut self.use_subtitles, "Use Subtitles")
+                            .on_hover_text("Use the video's subtitles as synced lyrics when none are given");
+                    }
+                    ui.label(&self.lyrics_status);
+                });
+
+                ui.label("Plain");
+                ui.text_edit_multiline(&mut self.lyrics);
+                ui.label("Synced (LRC)");
+                ui.add(egui::TextEdit::multiline(&mut self.synced_lyrics).code_editor());
             });
     }
-}
 
-impl Downloadable for Single {
-    fn download(&self, base_dir: &PathBuf) {
-        let download_dir = base_dir.join("singles");
-        let output_format = format!("{}---{}.%(ext)s", sanitize_filename::sanitize(&self.track), sanitize_filename::sanitize(&self.artist));
-        utils::download_video(&self.webpage_url, &output_format, download_dir.to_str().unwrap(), self.use_thumbnail);
+    /// Adds the subtitles yt-dlp wrote next to `stem` as lyrics, unless the song has synced lyrics already.
+    pub fn tag_subtitles(&self, mp3_path: &PathBuf, dir: &Path, stem: &str) -> Result<(), String> {
+        if !self.use_subtitles || !self.synced_lyrics.trim().is_empty() {
+            return Ok(())
+        }
 
-        let mp3_name = output_format.replace("%(ext)s", "mp3");
-        let mp3_path = &download_dir.join(mp3_name);
+        // not every video has subtitles
+        let subtitles = match lyrics::find_subtitles(dir, stem) {
+            Some(path) => lyrics::read_lrc(&path).map_err(|e| format!("subtitles: {}", e))?,
+            None => return Ok(())
+        };
+        let mut tag = Tag::read_from_path(mp3_path).map_err(|e| format!("subtitles: {}", e))?;
+        tag.remove_all_lyrics();
+        tag.remove_all_synchronised_lyrics();
+        lyrics::add_to_tag(&mut tag, &self.lyrics, &subtitles.synced);
+        tag.write_to_path(mp3_path, Version::Id3v24).map_err(|e| format!("subtitles: {}", e))
+    }
 
-        if let Some(path) = &self.cover_path {
-            let cover_name = path.file_name().unwrap();
-            let new_path = &download_dir.join(cover_name);
-            let _ = std::fs::copy(path, new_path);
+    /// Artist as written to the tag, featured artists included.
+    pub fn full_artist(&self) -> String {
+        if self.featured.is_empty() {
+            self.artist.to_string()
+        } else {
+            format!("{} feat. {}", self.artist, self.featured)
+        }
+    }
 
-            let cover_path = utils::convert_jpg(&new_path);
-            self.tag(mp3_path, &cover_path, None);
+    /// Cover to embed, either the picked image copied into `dir` or the thumbnail yt-dlp wrote
+    /// next to `thumbnail_stem`, run through the cover options.
+    /// `None` when there's nothing to embed, a video without a thumbnail included.
+    pub fn prepare_cover(&self, dir: &PathBuf, thumbnail_stem: &str, options: &CoverOptions) -> Result<Option<PathBuf>, String> {
+        let path = if let Some(path) = &self.cover_path {
+            let new_path = dir.join(path.file_name().ok_or("cover: not a file")?);
+            std::fs::copy(path, &new_path).map_err(|e| format!("cover: {}", e))?;
+            new_path
         } else if self.use_thumbnail {
-            let cover_name = output_format.replace("%(ext)s", "webp");
-            let cover_path = &download_dir.join(cover_name);
-            let cover_path = utils::convert_jpg(&cover_path);
-            self.tag(mp3_path, &cover_path, None);
+            match cover::find_thumbnail(dir, thumbnail_stem) {
+                Some(path) => path,
+                None => return Ok(None)
+            }
+        } else {
+            return Ok(None)
+        };
+
+        cover::prepare(&path, options).map(Some)
+    }
+
+    /// Where `layout` puts this song, `album_artist` is empty outside of albums.
+    pub fn place<'a>(&'a self, album_artist: &'a str, position: Option<Position>) -> Place<'a> {
+        Place {
+            artist: &self.artist,
+            album_artist,
+            album: &self.album,
+            year: &self.year,
+            track: &self.track,
+            track_no: position.map(|position| position.track),
+            disc: position.filter(|position| position.disc_total > 1).map(|position| position.disc)
         }
+    }
 
-        utils::cleanup(&download_dir);
+    /// Writes the song's tag to `mp3_path`, replacing whatever tag it had.
+    pub fn tag(&self, mp3_path: &PathBuf, cover_path: Option<&PathBuf>, position: Option<Position>, album_artist: Option<&str>) -> Result<(), String> {
+        let tag = self.build_tag(cover_path, position, album_artist)?;
+        tag.write_to_path(mp3_path, Version::Id3v24).map_err(|e| format!("tag: {}", e))
     }
-}
 
-impl Single {
-    pub fn tag(&self, mp3_path: &PathBuf, cover_path: &PathBuf, track_no: Option<usize>) {
+    fn build_tag(&self, cover_path: Option<&PathBuf>, position: Option<Position>, album_artist: Option<&str>) -> Result<Tag, String> {
         let mut tag = Tag::new();
 
         tag.set_title(&self.track);
-        tag.set_artist(&self.artist);
+        tag.set_artist(self.full_artist());
         tag.set_album(&self.album);
+        if let Some(album_artist) = album_artist.filter(|album_artist| !album_artist.is_empty()) {
+            tag.set_album_artist(album_artist);
+        }
 
-        if let Some(n) = track_no {
-            tag.set_track(n.try_into().unwrap());
+        if let Ok(year) = self.year.parse::<i32>() {
+            tag.set_year(year);
         }
 
-        match std::fs::read(&cover_path) {
-            Ok(bytes) => {
-                tag.add_frame(Picture{
-                    mime_type: "image/jpeg".to_string(),
-                    picture_type: PictureType::CoverFront,
-                    description: "".to_string(),
-                    data: bytes
+        let provenance = [
+            &self.webpage_url,
+            &self.video_id,
+            &self.uploader,
+            &self.fetch_date,
+            &self.musicbrainz_release_id,
+            &self.musicbrainz_recording_id,
+        ];
+        for (description, value) in PROVENANCE.iter().zip(provenance) {
+            if !value.is_empty() {
+                tag.add_frame(ExtendedText {
+                    description: description.to_string(),
+                    value: value.to_string()
                 });
-            },
-            _ => { println!("image file") }
+            }
+        }
+
+        if let Some(position) = position {
+            tag.set_track(position.track.try_into().unwrap());
+            if position.disc_total > 1 {
+                tag.set_disc(position.disc.try_into().unwrap());
+                tag.set_total_discs(position.disc_total.try_into().unwrap());
+            }
         }
 
-        tag.write_to_path(mp3_path, Version::Id3v24).expect("Failed to write tag");
+        lyrics::add_to_tag(&mut tag, &self.lyrics, &self.synced_lyrics);
+
+        if let Some(cover_path) = cover_path {
+            let bytes = std::fs::read(cover_path).map_err(|e| format!("cover: {}", e))?;
+            tag.add_frame(Picture{
+                mime_type: cover::mime_type(&bytes).to_string(),
+                picture_type: PictureType::CoverFront,
+                description: "".to_string(),
+                data: bytes
+            });
+        }
+
+        return Ok(tag)
+    }
+}
+
+/// Track numbers for an album's songs, counted per disc. `None` for excluded songs.
+/// With `renumber` only included songs are counted, otherwise songs keep their place in the list.
+/// Discs are numbered without gaps, so skipped disc numbers or discs left out entirely don't count.
+pub fn album_positions(songs: &[Single], renumber: bool) -> Vec<Option<Position>> {
+    let mut discs: Vec<usize> = songs.iter()
+        .filter(|song| song.include || !renumber)
+        .map(|song| song.disc)
+        .collect();
+    discs.sort();
+    discs.dedup();
+    let disc_total = discs.len().max(1);
+    let mut listed: HashMap<usize, usize> = HashMap::new();
+    let mut selected: HashMap<usize, usize> = HashMap::new();
+
+    songs.iter().map(|song| {
+        let listed_no = listed.entry(song.disc).or_default();
+        *listed_no += 1;
+        if !song.include {
+            return None
+        }
+        let selected_no = selected.entry(song.disc).or_default();
+        *selected_no += 1;
+
+        Some(Position {
+            track: if renumber { *selected_no } else { *listed_no },
+            disc: discs.binary_search(&song.disc).map(|i| i + 1).unwrap_or(song.disc),
+            disc_total
+        })
+    }).collect()
+}
+
+/// Applies a release's tracklist to the included songs, in order.
+/// With `discs` the songs are also moved to the release's discs, full video albums keep
+/// theirs since a disc there is the video its songs are cut from.
+pub fn apply_release(songs: &mut [Single], release: &Release, discs: bool) {
+    let included = songs.iter_mut().filter(|song| song.include);
+    for (song, track) in included.zip(&release.tracks) {
+        song.apply_release(release, track);
+        song.track_number = Some(track.position);
+        if discs {
+            song.disc = track.disc;
+        }
+    }
+}
+
+fn inferred_label(ui: &mut egui::Ui, inferred: bool) {
+    if inferred {
+        ui.weak("inferred").on_hover_text("Guessed from the video title");
+    }
+}
+
+/// Moving or removing a song in a list, applied once the list is rendered.
+#[derive(Clone, Copy)]
+pub enum SongEdit {
+    Up(usize),
+    Down(usize),
+    Remove(usize)
+}
+
+impl SongEdit {
+    pub fn render(ui: &mut egui::Ui, i: usize, len: usize, edit: &mut Option<SongEdit>) {
+        ui.horizontal(|ui| {
+            if ui.add_enabled(i > 0, egui::Button::new("Up")).clicked() {
+                *edit = Some(SongEdit::Up(i));
+            }
+            if ui.add_enabled(i + 1 < len, egui::Button::new("Down")).clicked() {
+                *edit = Some(SongEdit::Down(i));
+            }
+            if ui.button("Remove").clicked() {
+                *edit = Some(SongEdit::Remove(i));
+            }
+        });
+    }
+
+    pub fn apply<T>(self, items: &mut Vec<T>) {
+        match self {
+            SongEdit::Up(i) if i > 0 && i < items.len() => items.swap(i - 1, i),
+            SongEdit::Down(i) if i + 1 < items.len() => items.swap(i, i + 1),
+            SongEdit::Remove(i) if i < items.len() => { items.remove(i); },
+            _ => {}
+        }
+    }
+}
+
+/// Songs for fetched videos. With `split_chapters` a video with chapters becomes
+/// one song per chapter, trimmed to it.
+pub fn songs_from(urls: Vec<UrlInfo>, split_chapters: bool) -> Vec<Single> {
+    let mut songs = Vec::new();
+    for mut url in urls {
+        let chapters = if split_chapters { url.chapters.take().unwrap_or_default() } else { Vec::new() };
+        let video = Single::from(url);
+        if chapters.is_empty() {
+            songs.push(video);
+            continue;
+        }
+
+        for chapter in chapters {
+            let mut song = Single::default();
+            song.inherit_source(&video.webpage_url, &video.video_id, &video.uploader);
+            song.use_thumbnail = true;
+            song.thumbnail = video.thumbnail.to_string();
+            song.title = chapter.title;
+            song.album = video.album.to_string();
+            song.description = video.description.to_string();
+            song.duration = video.duration;
+            song.trim_start = chapter.start_time;
+            song.trim_end = chapter.end_time;
+
+            song.infer_from_title();
+            if song.track.is_empty() {
+                song.track = song.title.to_string();
+                song.inferred.track = true;
+            }
+            if song.artist.is_empty() {
+                song.artist = video.artist.to_string();
+                song.inferred.artist = true;
+            }
+            song.lookup.title = song.track.to_string();
+            song.lookup.artist = song.artist.to_string();
+            songs.push(song);
+        }
+    }
+    return songs
+}
+
+pub fn render_selection(ui: &mut egui::Ui, songs: &mut [Single]) {
+    ui.horizontal(|ui| {
+        let selected = songs.iter().filter(|song| song.include).count();
+        ui.label(format!("Selected {}/{}", selected, songs.len()));
+
+        if ui.button("All").clicked() {
+            for song in songs.iter_mut() {
+                song.include = true;
+            }
+        }
+        if ui.button("None").clicked() {
+            for song in songs.iter_mut() {
+                song.include = false;
+            }
+        }
+        if ui.button("Invert").clicked() {
+            for song in songs.iter_mut() {
+                song.include = !song.include;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(disc: usize, include: bool) -> Single {
+        let mut song = Single::default();
+        song.disc = disc;
+        song.include = include;
+        return song
+    }
+
+    fn numbers(positions: &[Option<Position>]) -> Vec<Option<(usize, usize, usize)>> {
+        positions.iter()
+            .map(|position| position.map(|position| (position.disc, position.track, position.disc_total)))
+            .collect()
+    }
+
+    #[test]
+    fn tracks_count_per_disc() {
+        let songs = [song(1, true), song(1, false), song(1, true), song(2, true)];
+        assert_eq!(numbers(&album_positions(&songs, true)), [Some((1, 1, 2)), None, Some((1, 2, 2)), Some((2, 1, 2))]);
+        assert_eq!(numbers(&album_positions(&songs, false)), [Some((1, 1, 2)), None, Some((1, 3, 2)), Some((2, 1, 2))]);
+    }
+
+    #[test]
+    fn releases_fill_the_included_songs() {
+        let track = |disc: usize, position: usize, recording_id: &str| ReleaseTrack {
+            recording_id: recording_id.to_string(),
+            title: recording_id.to_uppercase(),
+            artist: "Band".to_string(),
+            disc,
+            position
+        };
+        let mut release = Release::default();
+        release.id = "release".to_string();
+        release.title = "Album".to_string();
+        release.tracks = vec![track(1, 1, "a"), track(1, 2, "b"), track(2, 1, "c")];
+
+        let mut songs = [song(1, true), song(1, false), song(1, true), song(1, true)];
+        apply_release(&mut songs, &release, true);
+        let filled: Vec<(&str, usize, Option<usize>, &str)> = songs.iter()
+            .map(|song| (song.track.as_str(), song.disc, song.track_number, song.musicbrainz_recording_id.as_str()))
+            .collect();
+        assert_eq!(filled, [("A", 1, Some(1), "a"), ("", 1, None, ""), ("B", 1, Some(2), "b"), ("C", 2, Some(1), "c")]);
+        assert!(songs.iter().filter(|song| song.include).all(|song| song.album == "Album" && song.musicbrainz_release_id == "release"));
+
+        // discs of full video albums are their source videos
+        let mut songs = [song(1, true), song(1, true), song(1, true)];
+        apply_release(&mut songs, &release, false);
+        assert!(songs.iter().all(|song| song.disc == 1));
+    }
+
+    #[test]
+    fn discs_are_numbered_without_gaps() {
+        let songs = [song(1, true), song(3, true), song(5, true)];
+        assert_eq!(numbers(&album_positions(&songs, true)), [Some((1, 1, 3)), Some((2, 1, 3)), Some((3, 1, 3))]);
+
+        // a disc left out entirely only counts without renumbering
+        let songs = [song(1, true), song(2, false), song(3, true)];
+        assert_eq!(numbers(&album_positions(&songs, true)), [Some((1, 1, 2)), None, Some((2, 1, 2))]);
+        assert_eq!(numbers(&album_positions(&songs, false)), [Some((1, 1, 3)), None, Some((3, 1, 3))]);
+    }
+
+    #[test]
+    fn retag_keeps_frames_it_does_not_edit() {
+        let path = std::env::temp_dir().join(format!("bebops-test-retag-{}.mp3", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut old = Tag::new();
+        old.set_title("Old Title");
+        old.set_genre("Jazz");
+        old.write_to_path(&path, Version::Id3v24).unwrap();
+
+        let mut song = Single::default();
+        song.local_path = Some(path.to_path_buf());
+        song.use_thumbnail = true;
+        song.track = "New Title".to_string();
+        song.retag(&CoverOptions::default()).unwrap();
+
+        let tag = Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), Some("New Title"));
+        assert_eq!(tag.genre(), Some("Jazz"));
+        assert_eq!(tag.frames().filter(|frame| frame.id() == "TIT2").count(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn the_same_video_twice_gets_two_ids() {
+        let mut first = Single::default();
+        let mut second = Single::default();
+        for song in [&mut first, &mut second] {
+            song.webpage_url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string();
+            song.title = "Song".to_string();
+        }
+        assert_ne!(first.id(), second.id());
+    }
+
+    #[test]
+    fn clips_cut_their_own_segments() {
+        let mut options = SponsorBlockOptions::default();
+        options.enabled = true;
+        options.use_ytdlp = true;
+        let mut song = Single::default();
+        assert!(song.ytdlp_removes(&options));
+        song.trim_start = 30.0;
+        assert!(!song.ytdlp_removes(&options));
     }
 }
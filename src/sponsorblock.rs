@@ -0,0 +1,300 @@
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use crate::utils;
+
+// segments are looked up during downloads, a server that doesn't answer shouldn't hold them up
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+pub const CATEGORIES: [&str; 8] = [
+    "sponsor", "selfpromo", "interaction", "intro", "outro", "preview", "music_offtopic", "filler"
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum SegmentSource {
+    #[default]
+    Api,
+    File
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SponsorBlockOptions {
+    pub enabled: bool,
+    pub source: SegmentSource,
+    /// SponsorBlock compatible server, a local stand-in works too.
+    pub api_url: String,
+    pub file: Option<PathBuf>,
+    pub categories: Vec<String>,
+    /// Hand removal to yt-dlp's `--sponsorblock-remove` for untrimmed songs downloaded on their own.
+    pub use_ytdlp: bool
+}
+
+impl Default for SponsorBlockOptions {
+    fn default() -> Self {
+        SponsorBlockOptions {
+            enabled: false,
+            source: SegmentSource::Api,
+            api_url: "https://sponsor.ajay.app".to_string(),
+            file: None,
+            categories: vec!["sponsor".to_string(), "selfpromo".to_string(), "music_offtopic".to_string()],
+            use_ytdlp: false
+        }
+    }
+}
+
+impl SponsorBlockOptions {
+    /// yt-dlp can only fetch from an API, local files always go through `remove`.
+    pub fn ytdlp_removes(&self) -> bool {
+        self.enabled && self.use_ytdlp && self.source == SegmentSource::Api
+    }
+
+    /// Extra yt-dlp arguments when `ytdlp_removes`, empty otherwise.
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        if !self.ytdlp_removes() || self.categories.is_empty() {
+            return Vec::new()
+        }
+        vec![
+            "--sponsorblock-remove".to_string(),
+            self.categories.join(","),
+            "--sponsorblock-api".to_string(),
+            self.api_url.to_string()
+        ]
+    }
+
+    pub fn render(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Remove Segments");
+        ui.add_enabled_ui(self.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.source, SegmentSource::Api, "API");
+                ui.radio_value(&mut self.source, SegmentSource::File, "Local File");
+            });
+
+            match self.source {
+                SegmentSource::Api => {
+                    ui.horizontal(|ui| {
+                        ui.label("Server");
+                        ui.text_edit_singleline(&mut self.api_url);
+                    });
+                    ui.checkbox(&mut self.use_ytdlp, "Let yt-dlp Remove Segments")
+                        .on_hover_text("Full videos and trimmed songs are always cut by bebops so their times stay correct");
+                },
+                SegmentSource::File => {
+                    ui.horizontal(|ui| {
+                        if ui.button("Pick File").clicked() {
+                            let fd = rfd::FileDialog::new()
+                                .add_filter("json", &["json"]);
+
+                            if let Some(path) = fd.pick_file() {
+                                self.file = Some(path);
+                            }
+                        }
+
+                        if let Some(path) = &self.file {
+                            ui.monospace(path.to_string_lossy());
+                        }
+                    });
+                }
+            }
+
+            ui.horizontal_wrapped(|ui| {
+                for category in CATEGORIES {
+                    let mut checked = self.categories.iter().any(|c| c == category);
+                    if ui.checkbox(&mut checked, category).changed() {
+                        if checked {
+                            self.categories.push(category.to_string());
+                        } else {
+                            self.categories.retain(|c| c != category);
+                        }
+                    }
+                }
+            });
+        });
+    }
+}
+
+/// A segment as the SponsorBlock API returns it, times in seconds.
+/// `videoID` is only expected in local files, where entries without one apply to every video.
+#[derive(Deserialize, Clone)]
+pub struct Segment {
+    pub segment: [f32; 2],
+    pub category: String,
+    #[serde(rename = "videoID", default)]
+    pub video_id: Option<String>
+}
+
+/// Segments to cut out of `video_id` for the enabled categories.
+pub fn segments(video_id: &str, options: &SponsorBlockOptions) -> Result<Vec<Segment>, String> {
+    if !options.enabled || options.categories.is_empty() || video_id.is_empty() {
+        return Ok(Vec::new())
+    }
+
+    let segments: Vec<Segment> = match options.source {
+        SegmentSource::Api => {
+            let categories = serde_json::to_string(&options.categories).map_err(|e| e.to_string())?;
+            let response = ureq::get(&format!("{}/api/skipSegments", options.api_url.trim_end_matches('/')))
+                .query("videoID", video_id)
+                .query("categories", &categories)
+                .timeout(TIMEOUT)
+                .call();
+
+            match response {
+                Ok(response) => parse(&response.into_string().map_err(|e| e.to_string())?)?,
+                // the API answers 404 when a video has no segments
+                Err(ureq::Error::Status(404, _)) => Vec::new(),
+                Err(e) => return Err(e.to_string())
+            }
+        },
+        SegmentSource::File => {
+            let path = options.file.as_ref().ok_or("No segment file picked")?;
+            parse(&fs::read_to_string(path).map_err(|e| e.to_string())?)?
+        }
+    };
+
+    return Ok(segments.into_iter()
+        .filter(|segment| options.categories.contains(&segment.category))
+        .filter(|segment| segment.video_id.is_none() || segment.video_id.as_deref() == Some(video_id))
+        .collect())
+}
+
+/// Segments from an API response or a local file, both are a json list.
+fn parse(json: &str) -> Result<Vec<Segment>, String> {
+    serde_json::from_str(json).map_err(|e| e.to_string())
+}
+
+/// Cuts `segments` out of `mp3_path` in place. `offset` is where the file starts in the
+/// original video, so trimmed songs and chapters line up with the video's segment times.
+pub fn remove(mp3_path: &Path, segments: &[Segment], offset: f32) -> Result<(), String> {
+    let ranges: Vec<String> = segments.iter()
+        .map(|segment| (segment.segment[0] - offset, segment.segment[1] - offset))
+        .filter(|(_, end)| *end > 0.0)
+        .map(|(start, end)| format!("between(t,{:.3},{:.3})", start.max(0.0), end))
+        .collect();
+    if ranges.is_empty() {
+        return Ok(())
+    }
+
+    let filter = format!("aselect='not({})',asetpts=N/SR/TB", ranges.join("+"));
+    let cut = mp3_path.with_extension("cut.mp3");
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-y"])
+        .arg("-i").arg(mp3_path)
+        .args(["-af", &filter])
+        .args(["-c:a", "libmp3lame", "-q:a", "0"])
+        .arg(&cut)
+        .output()
+        .map_err(|e| format!("ffmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("segment removal: {}", utils::error_line(&output.stderr, "failed")))
+    }
+    fs::rename(&cut, mp3_path).map_err(|e| format!("segment removal: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const RESPONSE: &str = r#"[
+        {"category": "sponsor", "actionType": "skip", "segment": [12.5, 40.25], "UUID": "a1", "videoDuration": 300.1, "locked": 0, "votes": 3},
+        {"category": "intro", "actionType": "skip", "segment": [0, 8], "UUID": "b2", "videoDuration": 300.1, "locked": 1, "votes": 0}
+    ]"#;
+
+    fn options(api_url: &str) -> SponsorBlockOptions {
+        let mut options = SponsorBlockOptions::default();
+        options.enabled = true;
+        options.api_url = api_url.to_string();
+        options.categories = vec!["sponsor".to_string()];
+        return options
+    }
+
+    /// Stand-in server answering one request with `status` and `body`, returns its url and the request line it got.
+    fn serve(status: &str, body: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let status = status.to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string()
+        });
+        return (url, handle)
+    }
+
+    #[test]
+    fn parses_api_responses() {
+        let segments = parse(RESPONSE).unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].segment, [12.5, 40.25]);
+        assert_eq!(segments[0].category, "sponsor");
+        assert_eq!(segments[0].video_id, None);
+        assert!(parse("Not Found").is_err());
+    }
+
+    #[test]
+    fn fetches_enabled_categories_from_the_api() {
+        let (url, server) = serve("200 OK", RESPONSE);
+        let segments = segments("dQw4w9WgXcQ", &options(&url)).unwrap();
+        let request = server.join().unwrap();
+
+        assert!(request.starts_with("GET /api/skipSegments?"));
+        assert!(request.contains("videoID=dQw4w9WgXcQ"));
+        assert!(request.contains("sponsor"));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].category, "sponsor");
+    }
+
+    #[test]
+    fn videos_without_segments_are_not_errors() {
+        let (url, server) = serve("404 Not Found", "Not Found");
+        assert!(segments("dQw4w9WgXcQ", &options(&url)).unwrap().is_empty());
+        server.join().unwrap();
+
+        let (url, server) = serve("500 Internal Server Error", "");
+        assert!(segments("dQw4w9WgXcQ", &options(&url)).is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn local_files_are_filtered_by_video() {
+        let path = std::env::temp_dir().join(format!("bebops-test-segments-{}.json", std::process::id()));
+        fs::write(&path, r#"[
+            {"segment": [1, 2], "category": "sponsor", "videoID": "one"},
+            {"segment": [3, 4], "category": "sponsor", "videoID": "two"},
+            {"segment": [5, 6], "category": "sponsor"},
+            {"segment": [7, 8], "category": "intro"}
+        ]"#).unwrap();
+        let mut options = options("");
+        options.source = SegmentSource::File;
+        options.file = Some(path.to_path_buf());
+
+        let starts: Vec<f32> = segments("one", &options).unwrap().iter().map(|segment| segment.segment[0]).collect();
+        assert_eq!(starts, [1.0, 5.0]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn ytdlp_only_removes_from_the_api() {
+        let mut options = options("https://sponsor.example");
+        options.use_ytdlp = true;
+        assert_eq!(options.ytdlp_args(), ["--sponsorblock-remove", "sponsor", "--sponsorblock-api", "https://sponsor.example"]);
+        options.source = SegmentSource::File;
+        assert!(options.ytdlp_args().is_empty());
+    }
+}
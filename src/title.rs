@@ -0,0 +1,100 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+pub struct ParsedTitle {
+    pub artist: String,
+    pub track: String,
+    pub featured: Vec<String>
+}
+
+/// Guesses artist and track from common video title shapes:
+/// "Artist - Title", "Artist – Title (feat. X)" and "Title by Artist".
+/// The last one only counts when the artist is the `uploader`, plenty of songs are called "… by …".
+pub fn parse(title: &str, uploader: &str) -> Option<ParsedTitle> {
+    let (artist, track) = split_dash(title).or_else(|| split_by(title, uploader))?;
+
+    let mut featured = Vec::new();
+    let artist = take_featured(artist, &mut featured);
+    let track = take_featured(track, &mut featured);
+
+    if artist.is_empty() || track.is_empty() {
+        return None
+    }
+
+    Some(ParsedTitle { artist, track, featured })
+}
+
+fn split_dash(title: &str) -> Option<(&str, &str)> {
+    [" - ", " – ", " — "].iter()
+        .filter_map(|sep| title.split_once(sep))
+        .min_by_key(|(artist, _)| artist.len())
+}
+
+fn split_by<'a>(title: &'a str, uploader: &str) -> Option<(&'a str, &'a str)> {
+    static BY: OnceLock<Regex> = OnceLock::new();
+    let by = BY.get_or_init(|| Regex::new(r"(?i)^(.+)\s+by\s+(.+)$").unwrap());
+    let caps = by.captures(title)?;
+    let artist = caps.get(2)?.as_str();
+    if !is_uploader(artist, uploader) {
+        return None
+    }
+    Some((artist, caps.get(1)?.as_str()))
+}
+
+/// Whether `artist` names the channel, ignoring case, punctuation and YouTube's " - Topic"/"VEVO" suffixes.
+fn is_uploader(artist: &str, uploader: &str) -> bool {
+    let simplify = |name: &str| name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    let uploader = simplify(uploader.trim_end_matches(" - Topic"));
+    let uploader = uploader.strip_suffix("vevo").unwrap_or(&uploader);
+    !uploader.is_empty() && simplify(artist) == uploader
+}
+
+/// Strips "(feat. X)" / "ft. X" from `part`, pushing the names onto `featured`.
+fn take_featured(part: &str, featured: &mut Vec<String>) -> String {
+    static BRACKETED: OnceLock<Regex> = OnceLock::new();
+    static TRAILING: OnceLock<Regex> = OnceLock::new();
+    static SEPARATORS: OnceLock<Regex> = OnceLock::new();
+    let bracketed = BRACKETED.get_or_init(|| Regex::new(r"(?i)\s*[(\[](?:feat\.?|ft\.?|featuring)\s+([^)\]]+)[)\]]").unwrap());
+    let trailing = TRAILING.get_or_init(|| Regex::new(r"(?i)\s+(?:feat\.?|ft\.?|featuring)\s+(.+)$").unwrap());
+    let separators = SEPARATORS.get_or_init(|| Regex::new(r"\s*(?:,|&|\band\b)\s*").unwrap());
+
+    let mut rest = part.to_string();
+    for regex in [bracketed, trailing] {
+        if let Some(caps) = regex.captures(&rest) {
+            let names = caps.get(1).unwrap().as_str();
+            featured.extend(separators.split(names)
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty()));
+            rest = regex.replace(&rest, "").into_owned();
+        }
+    }
+
+    rest.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dash_titles_split_with_featured() {
+        let parsed = parse("Artist - Song (feat. One & Two)", "").unwrap();
+        assert_eq!(parsed.artist, "Artist");
+        assert_eq!(parsed.track, "Song");
+        assert_eq!(parsed.featured, ["One", "Two"]);
+    }
+
+    #[test]
+    fn by_titles_need_the_uploader() {
+        assert!(parse("Stand by Me", "Oldies Channel").is_none());
+        assert!(parse("Stand by Me", "").is_none());
+
+        let parsed = parse("Night Drive by The Band", "TheBandVEVO").unwrap();
+        assert_eq!(parsed.artist, "The Band");
+        assert_eq!(parsed.track, "Night Drive");
+        assert!(parse("Night Drive by The Band", "The Band - Topic").is_some());
+    }
+}
@@ -0,0 +1,169 @@
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use crate::utils;
+
+// quieter than this counts as silence
+const SILENCE_DB: i32 = -50;
+// shorter gaps are pauses in the song, not an intro or outro
+const MIN_SILENCE: f32 = 1.0;
+// how close to the start/end a silence has to reach to be trimmed
+const EDGE: f32 = 0.1;
+
+/// Cuts `mp3_path` in place down to `start..end` seconds, an `end` of 0 keeps the rest of the song.
+pub fn trim(mp3_path: &Path, start: f32, end: f32) -> Result<(), String> {
+    if start <= 0.0 && end <= 0.0 {
+        return Ok(())
+    }
+    let (start, end) = clamp(start, end, duration(mp3_path)?)?;
+
+    let trimmed = mp3_path.with_extension("trim.mp3");
+    extract(mp3_path, &trimmed, start, end)?;
+    fs::rename(&trimmed, mp3_path).map_err(|e| format!("trim: {}", e))
+}
+
+/// Copies `start..end` seconds of `source` to `dest` without re-encoding, an `end` of 0 copies to the end.
+pub fn extract(source: &Path, dest: &Path, start: f32, end: f32) -> Result<(), String> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-hide_banner", "-y"])
+        .args(["-ss", &start.to_string()]);
+    if end > start {
+        command.args(["-to", &end.to_string()]);
+    }
+    let output = command
+        .arg("-i").arg(source)
+        .args(["-c", "copy"])
+        .arg(dest)
+        .output()
+        .map_err(|e| format!("ffmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(utils::error_line(&output.stderr, "cutting failed"))
+    }
+    return Ok(())
+}
+
+/// `start` and `end` within a song of `duration` seconds, an `end` past the song keeps the rest of it.
+fn clamp(start: f32, end: f32, duration: f32) -> Result<(f32, f32), String> {
+    if start >= duration {
+        return Err(format!("trim: start is past the end of the song ({:.1}s)", duration))
+    }
+    let end = if end >= duration { 0.0 } else { end };
+    return Ok((start, end))
+}
+
+/// Length of `path` in seconds, from ffprobe.
+fn duration(path: &Path) -> Result<f32, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error"])
+        .args(["-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .map_err(|e| format!("ffprobe: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("ffprobe: {}", utils::error_line(&output.stderr, "failed")))
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().map_err(|_| "ffprobe: no duration".to_string())
+}
+
+/// Finds leading and trailing silence with ffmpeg silencedetect.
+/// Returns the `(start, end)` trim points, end is 0 when the song doesn't end in silence.
+pub fn detect_silence(audio_path: &Path) -> Result<(f32, f32), String> {
+    let filter = format!("silencedetect=noise={}dB:d={}", SILENCE_DB, MIN_SILENCE);
+    let output = Command::new("ffmpeg")
+        .args(["-hide_banner", "-nostats"])
+        .arg("-i").arg(audio_path)
+        .args(["-af", &filter])
+        .args(["-f", "null", "-"])
+        .output()
+        .map_err(|e| e.to_string())?;
+
+    parse_silence(&String::from_utf8_lossy(&output.stderr))
+}
+
+/// Trim points from silencedetect's output, see [`detect_silence`].
+fn parse_silence(stderr: &str) -> Result<(f32, f32), String> {
+    static START: OnceLock<Regex> = OnceLock::new();
+    static END: OnceLock<Regex> = OnceLock::new();
+    let start_re = START.get_or_init(|| Regex::new(r"silence_start: (-?[\d.]+)").unwrap());
+    let end_re = END.get_or_init(|| Regex::new(r"silence_end: ([\d.]+)").unwrap());
+
+    let duration = parse_duration(stderr).ok_or("Could not read duration")?;
+    let starts: Vec<f32> = start_re.captures_iter(stderr).filter_map(|caps| caps[1].parse().ok()).collect();
+    let ends: Vec<f32> = end_re.captures_iter(stderr).filter_map(|caps| caps[1].parse().ok()).collect();
+
+    let mut trim_start = 0.0;
+    let mut trim_end = 0.0;
+    for (i, silence_start) in starts.iter().enumerate() {
+        // the last silence may run to the end without a silence_end
+        let silence_end = ends.get(i).copied().unwrap_or(duration);
+        if *silence_start <= EDGE {
+            trim_start = silence_end;
+        } else if silence_end >= duration - EDGE {
+            trim_end = *silence_start;
+        }
+    }
+
+    if trim_start >= duration {
+        return Err("Entirely silent".to_string())
+    }
+    return Ok((trim_start, trim_end))
+}
+
+/// `Duration: 00:03:21.45` from ffmpeg's input summary, in seconds.
+fn parse_duration(stderr: &str) -> Option<f32> {
+    static DURATION: OnceLock<Regex> = OnceLock::new();
+    let re = DURATION.get_or_init(|| Regex::new(r"Duration: (\d+):(\d{2}):(\d{2}(?:\.\d+)?)").unwrap());
+    let caps = re.captures(stderr)?;
+    let hours: f32 = caps[1].parse().ok()?;
+    let mins: f32 = caps[2].parse().ok()?;
+    let secs: f32 = caps[3].parse().ok()?;
+    Some(hours * 3600.0 + mins * 60.0 + secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "Input #0, mp3, from 'probe.mp3':\n  Duration: 00:03:20.50, start: 0.025057, bitrate: 128 kb/s\n";
+
+    #[test]
+    fn durations_parse() {
+        assert_eq!(parse_duration(INPUT), Some(200.5));
+        assert_eq!(parse_duration("  Duration: 01:00:01, start: 0.0"), Some(3601.0));
+        assert_eq!(parse_duration("no duration"), None);
+    }
+
+    #[test]
+    fn edge_silences_become_trim_points() {
+        // ffmpeg puts a silence at the very start slightly below 0
+        let stderr = format!("{}[silencedetect @ 0x55] silence_start: -0.0123\n\
+            [silencedetect @ 0x55] silence_end: 2.5 | silence_duration: 2.51\n\
+            [silencedetect @ 0x55] silence_start: 100\n\
+            [silencedetect @ 0x55] silence_end: 102 | silence_duration: 2\n\
+            [silencedetect @ 0x55] silence_start: 195.25\n\
+            [silencedetect @ 0x55] silence_end: 200.5 | silence_duration: 5.25\n", INPUT);
+        assert_eq!(parse_silence(&stderr), Ok((2.5, 195.25)));
+
+        // the trailing silence runs to the end without a silence_end
+        let stderr = format!("{}[silencedetect @ 0x55] silence_start: 0.05\n\
+            [silencedetect @ 0x55] silence_end: 1.5 | silence_duration: 1.45\n\
+            [silencedetect @ 0x55] silence_start: 190\n", INPUT);
+        assert_eq!(parse_silence(&stderr), Ok((1.5, 190.0)));
+
+        assert_eq!(parse_silence(INPUT), Ok((0.0, 0.0)));
+        assert!(parse_silence(&format!("{}[silencedetect @ 0x55] silence_start: 0\n", INPUT)).is_err());
+        assert!(parse_silence("[silencedetect @ 0x55] silence_start: 0\n").is_err());
+    }
+
+    #[test]
+    fn trim_points_stay_within_the_song() {
+        assert_eq!(clamp(5.0, 150.0, 200.0), Ok((5.0, 150.0)));
+        assert_eq!(clamp(5.0, 250.0, 200.0), Ok((5.0, 0.0)));
+        assert_eq!(clamp(0.0, 200.0, 200.0), Ok((0.0, 0.0)));
+        assert!(clamp(210.0, 0.0, 200.0).is_err());
+    }
+}
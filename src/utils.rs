@@ -1,51 +1,269 @@
-use std::ffi::OsStr;
+use eframe::egui;
+use std::collections::HashSet;
 use std::fs;
 use std::process::Command;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn download_video(webpage_url: &str, output_format: &str, dir: &str, thumbnail: bool) {
+extern crate sanitize_filename;
+
+/// Sanitized file name part, never empty.
+pub fn sanitize_or(name: &str, fallback: &str) -> String {
+    let sanitized = sanitize_filename::sanitize(name.trim());
+    if sanitized.trim().is_empty() {
+        fallback.to_string()
+    } else {
+        sanitized
+    }
+}
+
+/// `track---artist` file stem used for downloaded songs.
+pub fn song_stem(track: &str, artist: &str) -> String {
+    format!("{}---{}", sanitize_or(track, "Untitled"), sanitize_or(artist, "Unknown Artist"))
+}
+
+/// Appends " (2)", " (3)"... to `stem` until it is not in `taken`, then records it.
+/// Compared case-insensitively so names don't clash on Windows and macOS.
+pub fn unique_stem(stem: String, taken: &mut HashSet<String>) -> String {
+    let mut unique = stem.clone();
+    let mut n = 2;
+    while taken.contains(&unique.to_lowercase()) {
+        unique = format!("{} ({})", stem, n);
+        n += 1;
+    }
+    taken.insert(unique.to_lowercase());
+    return unique
+}
+
+/// Deserializes `null` as the default, yt-dlp prints missing fields as `null`.
+pub fn null_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: serde::Deserializer<'de>, T: Default + serde::Deserialize<'de>
+{
+    Ok(<Option<T> as serde::Deserialize>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Seconds as `HH:MM:SS`.
+pub fn format_time(seconds: f32) -> String {
+    let n = seconds as i32;
+    let hours = n / (60 * 60);
+    let mins = (n / 60) % 60;
+    let secs = n % 60;
+    return format!("{hours:02}:{mins:02}:{secs:02}")
+}
+
+/// Drag box for a time in seconds, shown and typed as `HH:MM:SS`.
+pub fn time_edit(seconds: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(seconds)
+        .clamp_range(0..=((60 * 60 * 24) - 1))
+        .custom_formatter(|n, _| format_time(n as f32))
+        .custom_parser(|s| {
+            let parts: Vec<&str> = s.split(':').collect();
+            if parts.len() == 3 {
+                parts[0].parse::<i32>().and_then(|h| {
+                    parts[1].parse::<i32>().and_then(|m| {
+                        parts[2].parse::<i32>().map(|s| {
+                            return ((h * 60 * 60) + (m * 60) + s) as f64
+                        })
+                    })
+                })
+                .ok()
+            } else {
+                return None
+            }
+        })
+}
+
+/// Lowercased stems of the files already in `dir`, to seed `unique_stem` with.
+pub fn existing_stems(dir: &Path) -> HashSet<String> {
+    fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.path().file_stem().map(|stem| stem.to_string_lossy().to_lowercase()))
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Downloads `webpage_url` as mp3 into `dir`, errors with the last line yt-dlp printed.
+pub fn download_video(webpage_url: &str, output_format: &str, dir: &Path, thumbnail: bool, subtitles: bool, extra_args: &[String]) -> Result<(), String> {
     let mut binding = Command::new("yt-dlp");
     let command = binding
         .arg(webpage_url)
         .args(["-o", output_format])
-        .args(["--paths", dir])
+        .arg("--paths").arg(dir)
         .arg("--extract-audio")
         .args(["--audio-format", "mp3"]);
 
     if thumbnail {
         command.arg("--write-thumbnail");
     }
-    command.output().expect("download command failed");
+    if subtitles {
+        command.arg("--write-subs")
+            .args(["--convert-subs", "lrc"]);
+    }
+    command.args(extra_args);
+    let output = command.output().map_err(|e| format!("yt-dlp: {}", e))?;
+    if !output.status.success() {
+        return Err(error_line(&output.stderr, "download failed"))
+    }
+    return Ok(())
 }
 
-pub fn convert_jpg(path: &PathBuf) -> PathBuf {
-    let new_path = path.to_string_lossy().replace(path.extension().unwrap().to_str().unwrap(), "jpg");
-    Command::new("ffmpeg")
-        .arg("-i")
-        .arg(&path)
-        .arg(&new_path)
-        .output()
-        .expect("ffmpeg image conversion failed");
+/// Last line a command printed to stderr, where yt-dlp and ffmpeg put the actual error.
+pub fn error_line(stderr: &[u8], fallback: &str) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    stderr.lines().rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or(fallback)
+        .to_string()
+}
 
-    return PathBuf::from(new_path);
+/// Blocking work (network requests, yt-dlp, ffmpeg) running on its own thread so the UI stays responsive.
+/// Keep it in the view's state and `poll` it every frame until the result comes in.
+pub struct Task<T> {
+    receiver: Receiver<Result<T, String>>
 }
 
-pub fn cleanup(dir: &PathBuf) {
-    // remove image files and full videos
-    for entry in fs::read_dir(dir).expect("read_dir failed") {
-        let entry = entry.expect("failed to get entry");
-        if entry.file_name().to_string_lossy().contains("---FULL") {
-            let _ = std::fs::remove_file(&entry.path());
-            continue;
+impl<T: Send + 'static> Task<T> {
+    /// Starts `work`, the UI is repainted once it is done so `poll` picks the result up.
+    pub fn spawn(ctx: &egui::Context, work: impl FnOnce() -> Result<T, String> + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            // the view was cleared if nobody is listening anymore
+            let _ = sender.send(work());
+            ctx.request_repaint();
+        });
+        Task { receiver }
+    }
+
+    /// The result once the work is done, `None` while it is still running.
+    pub fn poll(&self) -> Option<Result<T, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("stopped unexpectedly".to_string()))
         }
-        if let Some(extension) = Path::new(&entry.path()).extension().and_then(OsStr::to_str) {
-            match extension {
-                "jpg" => { let _ = std::fs::remove_file(&entry.path()); },
-                "jpeg" => { let _ = std::fs::remove_file(&entry.path()); },
-                "png" => { let _ = std::fs::remove_file(&entry.path()); },
-                "webp" => { let _ = std::fs::remove_file(&entry.path()); },
-                _ => {}
-            }
+    }
+}
+
+/// Scratch directory for a single download, inside the download directory so finished songs can be renamed into place.
+/// yt-dlp output, thumbnails, copied covers and full videos all go here, only finished
+/// songs are moved out with `keep`. Whatever is left is removed when the job is dropped,
+/// so nothing that was already in the folder (or belongs to another job) is ever touched.
+pub struct Job {
+    pub dir: PathBuf
+}
+
+impl Job {
+    pub fn new(download_dir: &Path) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+        let dir = download_dir.join(format!(".bebops-{}-{}", std::process::id(), nanos));
+        fs::create_dir_all(&dir).expect("failed to create job directory");
+
+        Job { dir }
+    }
+
+    /// Moves a finished file from the job directory to `dest`, creating its folders as needed.
+    /// A file already at `dest` is never replaced, the song gets a free name next to it instead.
+    /// Returns where the file ended up.
+    pub fn keep(&self, file: &Path, dest: &Path) -> Result<PathBuf, String> {
+        let dest = free_path(dest);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("{}: {}", parent.display(), e))?;
         }
+        if fs::rename(file, &dest).is_err() {
+            // rename fails across filesystems, fall back to copying
+            fs::copy(file, &dest).map_err(|e| format!("{}: {}", dest.display(), e))?;
+        }
+        return Ok(dest)
+    }
+}
+
+/// `path`, or the first `stem (n)` next to it that no file in its folder uses yet.
+pub fn free_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf()
+    }
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let mut taken = existing_stems(dir);
+    let stem = unique_stem(stem, &mut taken);
+    match path.extension() {
+        Some(extension) => dir.join(format!("{}.{}", stem, extension.to_string_lossy())),
+        None => dir.join(stem)
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bebops-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir
+    }
+
+    #[test]
+    fn keep_moves_into_new_folders() {
+        let base = scratch("keep-new");
+        let job = Job::new(&base);
+        let file = job.dir.join("Song.mp3");
+        fs::write(&file, "new").unwrap();
+
+        let dest = base.join("Artist").join("Song.mp3");
+        assert_eq!(job.keep(&file, &dest).unwrap(), dest);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert!(!file.exists());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn keep_leaves_foreign_files_alone() {
+        let base = scratch("keep-foreign");
+        fs::write(base.join("Song.mp3"), "foreign").unwrap();
+        fs::write(base.join("song (2).flac"), "foreign").unwrap();
+        let job = Job::new(&base);
+        let file = job.dir.join("Song.mp3");
+        fs::write(&file, "new").unwrap();
+
+        let kept = job.keep(&file, &base.join("Song.mp3")).unwrap();
+        assert_eq!(kept, base.join("Song (3).mp3"));
+        assert_eq!(fs::read_to_string(base.join("Song.mp3")).unwrap(), "foreign");
+        assert_eq!(fs::read_to_string(base.join("song (2).flac")).unwrap(), "foreign");
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "new");
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn keep_fails_for_missing_files() {
+        let base = scratch("keep-missing");
+        let job = Job::new(&base);
+
+        assert!(job.keep(&job.dir.join("Missing.mp3"), &base.join("Missing.mp3")).is_err());
+        assert!(!base.join("Missing.mp3").exists());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn unique_stem_ignores_case() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_stem("Song".to_string(), &mut taken), "Song");
+        assert_eq!(unique_stem("SONG".to_string(), &mut taken), "SONG (2)");
+        assert_eq!(unique_stem("song".to_string(), &mut taken), "song (3)");
     }
 }